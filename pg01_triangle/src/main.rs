@@ -1,3 +1,4 @@
+use glfw::{Action, Key, WindowEvent};
 use runtime::{App, AppContext};
 
 struct MyApp;
@@ -7,13 +8,27 @@ impl App for MyApp {
         Ok(String::from("Triangle"))
     }
 
-    fn frame(&mut self, ctx: &mut AppContext) -> anyhow::Result<()> {
+    fn frame(&mut self, _ctx: &mut AppContext, _window: usize) -> anyhow::Result<()> {
         // let idx = ctx.acquire_next_image_from_swapchain(u64::MAX, None, None)?;
         Ok(())
     }
+
+    fn event(
+        &mut self,
+        ctx: &mut AppContext,
+        _window: usize,
+        event: WindowEvent,
+    ) -> anyhow::Result<()> {
+        // F12 and Print Screen are taken by RenderDoc's own capture keys
+        if let WindowEvent::Key(Key::C, _, Action::Press, _) = event {
+            ctx.trigger_renderdoc_capture();
+        }
+        Ok(())
+    }
 }
 
 fn main() {
+    env_logger::init();
     let app = MyApp {};
     runtime::run(app).unwrap();
 }
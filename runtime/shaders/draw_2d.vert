@@ -0,0 +1,17 @@
+#version 450
+
+layout(location = 0) in vec2 in_position;
+layout(location = 1) in vec4 in_color;
+
+layout(location = 0) out vec4 out_color;
+
+// maps pixel coordinates (origin top left) to clip space
+layout(push_constant) uniform PushConstants {
+    vec2 screen_size;
+} pc;
+
+void main() {
+    vec2 ndc = in_position / pc.screen_size * 2.0 - 1.0;
+    gl_Position = vec4(ndc, 0.0, 1.0);
+    out_color = in_color;
+}
@@ -0,0 +1,136 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::bail;
+
+// searched before the default directories when set
+pub const ASSET_DIR_ENV_VAR: &str = "VK_PLAYGROUND_ASSET_DIR";
+
+// finds assets independently of the working directory the demo was started from
+pub struct AssetResolver {
+    search_paths: Vec<PathBuf>,
+}
+
+impl AssetResolver {
+    pub fn new(search_paths: Vec<PathBuf>) -> Self {
+        Self { search_paths }
+    }
+
+    // $VK_PLAYGROUND_ASSET_DIR, the executable's directory, $CARGO_MANIFEST_DIR (set by
+    // `cargo run`) and the working directory, in that order
+    pub fn default_search_paths() -> Vec<PathBuf> {
+        let env_dir = |name: &str| std::env::var_os(name).map(PathBuf::from);
+        let exe_dir = std::env::current_exe()
+            .ok()
+            .and_then(|e| e.parent().map(Path::to_path_buf));
+        [
+            env_dir(ASSET_DIR_ENV_VAR),
+            exe_dir,
+            env_dir("CARGO_MANIFEST_DIR"),
+            std::env::current_dir().ok(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    pub fn search_paths(&self) -> &[PathBuf] {
+        &self.search_paths
+    }
+
+    // first existing `search path / relative`, absolute paths are returned as is if they exist
+    pub fn resolve(&self, relative: &str) -> anyhow::Result<PathBuf> {
+        let relative = Path::new(relative);
+        if relative.is_absolute() {
+            if relative.exists() {
+                return Ok(relative.to_path_buf());
+            }
+            bail!("asset {} doesn't exist", relative.display());
+        }
+
+        let candidates: Vec<_> = self.search_paths.iter().map(|e| e.join(relative)).collect();
+        if let Some(path) = candidates.iter().find(|e| e.exists()) {
+            return Ok(path.clone());
+        }
+
+        let searched: Vec<_> = candidates.iter().map(|e| e.display().to_string()).collect();
+        bail!(
+            "asset {} not found, searched: {}",
+            relative.display(),
+            searched.join(", ")
+        );
+    }
+}
+
+impl Default for AssetResolver {
+    fn default() -> Self {
+        Self::new(Self::default_search_paths())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // removed again when dropped
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "vk-playground-assets-{}-{name}",
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn relative_paths_resolve_to_the_first_search_path_containing_them() {
+        let first = TempDir::new("relative-first");
+        let second = TempDir::new("relative-second");
+        std::fs::create_dir_all(second.0.join("textures")).unwrap();
+        std::fs::write(second.0.join("textures/a.png"), []).unwrap();
+        let resolver = AssetResolver::new(vec![first.0.clone(), second.0.clone()]);
+        assert_eq!(
+            resolver.resolve("textures/a.png").unwrap(),
+            second.0.join("textures/a.png")
+        );
+
+        std::fs::create_dir_all(first.0.join("textures")).unwrap();
+        std::fs::write(first.0.join("textures/a.png"), []).unwrap();
+        assert_eq!(
+            resolver.resolve("textures/a.png").unwrap(),
+            first.0.join("textures/a.png")
+        );
+    }
+
+    #[test]
+    fn absolute_paths_skip_the_search_paths() {
+        let dir = TempDir::new("absolute");
+        let path = dir.0.join("a.spv");
+        std::fs::write(&path, []).unwrap();
+        let resolver = AssetResolver::new(vec![]);
+        assert_eq!(resolver.resolve(path.to_str().unwrap()).unwrap(), path);
+
+        let missing = dir.0.join("missing.spv");
+        assert!(resolver.resolve(missing.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn missing_assets_list_the_searched_paths() {
+        let dir = TempDir::new("missing");
+        let resolver = AssetResolver::new(vec![dir.0.clone()]);
+        let err = resolver.resolve("missing.png").unwrap_err().to_string();
+        assert!(
+            err.contains(&dir.0.join("missing.png").display().to_string()),
+            "{err}"
+        );
+    }
+}
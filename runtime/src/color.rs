@@ -0,0 +1,199 @@
+use ash::vk::{ColorSpaceKHR, Format};
+
+use crate::vk_utils::is_srgb_format;
+
+// luminance of 1.0 when mapping to absolute (PQ) encodings, the BT.2408 reference white
+pub const SDR_WHITE_NITS: f32 = 203.0;
+
+// HLG scene light that encodes to the 75% reference white signal
+const HLG_REFERENCE_WHITE: f32 = 0.2647;
+
+const REC709_TO_REC2020: [[f32; 3]; 3] = [
+    [0.627_404, 0.329_282, 0.043_314],
+    [0.069_097, 0.919_540, 0.011_361],
+    [0.016_392, 0.088_013, 0.895_595],
+];
+
+const REC709_TO_DISPLAY_P3: [[f32; 3]; 3] = [
+    [0.822_462, 0.177_538, 0.0],
+    [0.033_194, 0.966_806, 0.0],
+    [0.017_083, 0.072_397, 0.910_520],
+];
+
+// `color` is linear with sRGB primaries, 1.0 being SDR white, alpha is passed through
+// for SRGB_NONLINEAR the result stays linear since _SRGB formats encode on write, use
+// `encode_for_swapchain` to also handle UNORM swapchains
+pub fn encode_for_colorspace(color: [f32; 4], color_space: ColorSpaceKHR) -> [f32; 4] {
+    let linear = to_primaries([color[0], color[1], color[2]], color_space);
+    let encoded = match color_space {
+        ColorSpaceKHR::EXTENDED_SRGB_NONLINEAR_EXT | ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT => {
+            linear.map(srgb_oetf)
+        }
+        ColorSpaceKHR::HDR10_ST2084_EXT => linear.map(|e| pq_oetf(e * SDR_WHITE_NITS / 10_000.0)),
+        ColorSpaceKHR::HDR10_HLG_EXT => linear.map(|e| hlg_oetf(e * HLG_REFERENCE_WHITE)),
+        // SRGB_NONLINEAR, EXTENDED_SRGB_LINEAR (scRGB, 1.0 is SDR white already) and
+        // linear or pass through spaces
+        _ => linear,
+    };
+    [encoded[0], encoded[1], encoded[2], color[3]]
+}
+
+// like `encode_for_colorspace` but accounts for the format, _SRGB formats apply the sRGB
+// transfer function on write so the color space's one is skipped, while a SRGB_NONLINEAR
+// swapchain that fell back to a UNORM format gets it applied here
+pub fn encode_for_swapchain(
+    color: [f32; 4],
+    format: Format,
+    color_space: ColorSpaceKHR,
+) -> [f32; 4] {
+    if is_srgb_format(format) {
+        let linear = to_primaries([color[0], color[1], color[2]], color_space);
+        return [linear[0], linear[1], linear[2], color[3]];
+    }
+    let encoded = encode_for_colorspace(color, color_space);
+    if color_space == ColorSpaceKHR::SRGB_NONLINEAR {
+        return [
+            srgb_oetf(encoded[0]),
+            srgb_oetf(encoded[1]),
+            srgb_oetf(encoded[2]),
+            encoded[3],
+        ];
+    }
+    encoded
+}
+
+// still linear, converted to the primaries of `color_space`
+fn to_primaries(rgb: [f32; 3], color_space: ColorSpaceKHR) -> [f32; 3] {
+    match color_space {
+        ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT | ColorSpaceKHR::DISPLAY_P3_LINEAR_EXT => {
+            transform(REC709_TO_DISPLAY_P3, rgb)
+        }
+        ColorSpaceKHR::BT2020_LINEAR_EXT
+        | ColorSpaceKHR::HDR10_ST2084_EXT
+        | ColorSpaceKHR::HDR10_HLG_EXT => transform(REC709_TO_REC2020, rgb),
+        _ => rgb,
+    }
+}
+
+fn transform(matrix: [[f32; 3]; 3], rgb: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2])
+}
+
+// mirrored for negative values so extended ranges survive
+fn srgb_oetf(linear: f32) -> f32 {
+    let magnitude = linear.abs();
+    let encoded = if magnitude <= 0.003_130_8 {
+        magnitude * 12.92
+    } else {
+        1.055 * magnitude.powf(1.0 / 2.4) - 0.055
+    };
+    encoded.copysign(linear)
+}
+
+// SMPTE ST 2084, `linear` is normalized to 10000 nits
+fn pq_oetf(linear: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+    let y = linear.clamp(0.0, 1.0).powf(M1);
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
+
+// ARIB STD-B67, `linear` is normalized scene light
+fn hlg_oetf(linear: f32) -> f32 {
+    const A: f32 = 0.178_832_77;
+    const B: f32 = 0.284_668_92;
+    const C: f32 = 0.559_910_7;
+
+    let linear = linear.clamp(0.0, 1.0);
+    if linear <= 1.0 / 12.0 {
+        (3.0 * linear).sqrt()
+    } else {
+        A * (12.0 * linear - B).ln() + C
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: [f32; 4], expected: [f32; 4]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-4, "{actual:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn srgb_oetf_matches_reference_values() {
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_oetf(0.5) - 0.735_357).abs() < 1e-5);
+        // linear segment
+        assert!((srgb_oetf(0.002) - 0.025_84).abs() < 1e-6);
+        // mirrored for extended ranges
+        assert_eq!(srgb_oetf(-0.5), -srgb_oetf(0.5));
+    }
+
+    #[test]
+    fn pq_and_hlg_oetf_cover_their_range() {
+        assert!(pq_oetf(0.0) < 1e-6);
+        assert!((pq_oetf(1.0) - 1.0).abs() < 1e-5);
+        assert!((hlg_oetf(1.0 / 12.0) - 0.5).abs() < 1e-6);
+        assert!((hlg_oetf(1.0) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn srgb_format_is_left_linear() {
+        let color = [0.5, 0.25, 0.0, 0.5];
+        assert_close(
+            encode_for_swapchain(color, Format::B8G8R8A8_SRGB, ColorSpaceKHR::SRGB_NONLINEAR),
+            color,
+        );
+        assert_close(
+            encode_for_swapchain(
+                color,
+                Format::R8G8B8A8_SRGB,
+                ColorSpaceKHR::EXTENDED_SRGB_NONLINEAR_EXT,
+            ),
+            color,
+        );
+    }
+
+    #[test]
+    fn unorm_format_gets_the_transfer_function() {
+        let color = [0.5, 0.25, 0.0, 0.5];
+        let expected = [srgb_oetf(0.5), srgb_oetf(0.25), 0.0, 0.5];
+        assert_close(
+            encode_for_swapchain(color, Format::B8G8R8A8_UNORM, ColorSpaceKHR::SRGB_NONLINEAR),
+            expected,
+        );
+        assert_close(
+            encode_for_swapchain(
+                color,
+                Format::A2B10G10R10_UNORM_PACK32,
+                ColorSpaceKHR::EXTENDED_SRGB_NONLINEAR_EXT,
+            ),
+            expected,
+        );
+    }
+
+    #[test]
+    fn white_maps_to_white_in_wide_gamuts() {
+        let white = [1.0, 1.0, 1.0, 1.0];
+        assert_close(
+            encode_for_colorspace(white, ColorSpaceKHR::DISPLAY_P3_LINEAR_EXT),
+            white,
+        );
+        assert_close(
+            encode_for_colorspace(white, ColorSpaceKHR::BT2020_LINEAR_EXT),
+            white,
+        );
+        assert_close(
+            encode_for_colorspace(white, ColorSpaceKHR::DISPLAY_P3_NONLINEAR_EXT),
+            white,
+        );
+    }
+}
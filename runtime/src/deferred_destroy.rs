@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+
+use ash::vk::{
+    Buffer, DescriptorPool, DescriptorSetLayout, Image, ImageView, Pipeline, PipelineLayout,
+    Sampler, ShaderModule,
+};
+use gpu_allocator::vulkan::Allocation;
+
+use crate::vk_utils::{AllocatedBuffer, AttachmentImage, Texture2D, UniformBuffer};
+use crate::Vk;
+
+pub type DestroyFn = Box<dyn FnOnce(&mut Vk) -> anyhow::Result<()>>;
+
+// anything that may still be referenced by a command buffer in flight
+pub enum DeferredResource {
+    Buffer(Buffer, Allocation),
+    Image(Image, Allocation),
+    ImageView(ImageView),
+    Sampler(Sampler),
+    Pipeline(Pipeline),
+    PipelineLayout(PipelineLayout),
+    ShaderModule(ShaderModule),
+    DescriptorPool(DescriptorPool),
+    DescriptorSetLayout(DescriptorSetLayout),
+    Texture(Texture2D),
+    Attachment(AttachmentImage),
+    // for resources without a variant, called once the gpu is done with them
+    Custom(DestroyFn),
+}
+
+impl DeferredResource {
+    fn destroy(self, vk: &mut Vk) -> anyhow::Result<()> {
+        let device = vk.device();
+        unsafe {
+            match self {
+                DeferredResource::Buffer(buffer, allocation) => {
+                    return vk.destroy_buffer(buffer, allocation)
+                }
+                DeferredResource::Image(image, allocation) => {
+                    return vk.destroy_image(image, allocation)
+                }
+                DeferredResource::ImageView(view) => device.destroy_image_view(view, None),
+                DeferredResource::Sampler(sampler) => device.destroy_sampler(sampler, None),
+                DeferredResource::Pipeline(pipeline) => device.destroy_pipeline(pipeline, None),
+                DeferredResource::PipelineLayout(layout) => vk.destroy_pipeline_layout(layout),
+                DeferredResource::ShaderModule(module) => {
+                    device.destroy_shader_module(module, None)
+                }
+                DeferredResource::DescriptorPool(pool) => {
+                    device.destroy_descriptor_pool(pool, None)
+                }
+                DeferredResource::DescriptorSetLayout(layout) => {
+                    device.destroy_descriptor_set_layout(layout, None)
+                }
+                DeferredResource::Texture(texture) => return texture.destroy(vk),
+                DeferredResource::Attachment(attachment) => return attachment.destroy(vk),
+                DeferredResource::Custom(destroy) => return destroy(vk),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl From<(Buffer, Allocation)> for DeferredResource {
+    fn from((buffer, allocation): (Buffer, Allocation)) -> Self {
+        DeferredResource::Buffer(buffer, allocation)
+    }
+}
+
+impl From<(Image, Allocation)> for DeferredResource {
+    fn from((image, allocation): (Image, Allocation)) -> Self {
+        DeferredResource::Image(image, allocation)
+    }
+}
+
+impl From<ImageView> for DeferredResource {
+    fn from(view: ImageView) -> Self {
+        DeferredResource::ImageView(view)
+    }
+}
+
+impl From<Sampler> for DeferredResource {
+    fn from(sampler: Sampler) -> Self {
+        DeferredResource::Sampler(sampler)
+    }
+}
+
+impl From<Pipeline> for DeferredResource {
+    fn from(pipeline: Pipeline) -> Self {
+        DeferredResource::Pipeline(pipeline)
+    }
+}
+
+impl From<PipelineLayout> for DeferredResource {
+    fn from(layout: PipelineLayout) -> Self {
+        DeferredResource::PipelineLayout(layout)
+    }
+}
+
+impl From<ShaderModule> for DeferredResource {
+    fn from(module: ShaderModule) -> Self {
+        DeferredResource::ShaderModule(module)
+    }
+}
+
+impl From<DescriptorPool> for DeferredResource {
+    fn from(pool: DescriptorPool) -> Self {
+        DeferredResource::DescriptorPool(pool)
+    }
+}
+
+impl From<DescriptorSetLayout> for DeferredResource {
+    fn from(layout: DescriptorSetLayout) -> Self {
+        DeferredResource::DescriptorSetLayout(layout)
+    }
+}
+
+impl From<Texture2D> for DeferredResource {
+    fn from(texture: Texture2D) -> Self {
+        DeferredResource::Texture(texture)
+    }
+}
+
+impl From<AttachmentImage> for DeferredResource {
+    fn from(attachment: AttachmentImage) -> Self {
+        DeferredResource::Attachment(attachment)
+    }
+}
+
+impl From<AllocatedBuffer> for DeferredResource {
+    fn from(buffer: AllocatedBuffer) -> Self {
+        DeferredResource::Custom(Box::new(move |_| {
+            drop(buffer);
+            Ok(())
+        }))
+    }
+}
+
+impl<T: Copy + 'static> From<UniformBuffer<T>> for DeferredResource {
+    fn from(uniform_buffer: UniformBuffer<T>) -> Self {
+        DeferredResource::Custom(Box::new(move |_| {
+            drop(uniform_buffer);
+            Ok(())
+        }))
+    }
+}
+
+// resources are destroyed once the frame they were scheduled on can no longer be in flight,
+// which relies on the app signaling the frame latency fence
+#[derive(Default)]
+pub(crate) struct DeferredDestroyQueue {
+    // ordered by the frame they were scheduled on
+    pending: VecDeque<(u64, DeferredResource)>,
+}
+
+impl DeferredDestroyQueue {
+    pub fn push(&mut self, frame_idx: u64, resource: DeferredResource) {
+        self.pending.push_back((frame_idx, resource));
+    }
+
+    // must be called after waiting on the frame latency fence of `frame_idx`
+    pub fn destroy_expired(
+        &mut self,
+        vk: &mut Vk,
+        frame_idx: u64,
+        frames_in_flight: u64,
+    ) -> anyhow::Result<()> {
+        while let Some((scheduled_frame, _)) = self.pending.front() {
+            if scheduled_frame + frames_in_flight > frame_idx {
+                break;
+            }
+            let (_, resource) = self.pending.pop_front().unwrap();
+            resource.destroy(vk)?;
+        }
+
+        Ok(())
+    }
+
+    // the device must be idle
+    pub fn destroy_all(&mut self, vk: &mut Vk) -> anyhow::Result<()> {
+        while let Some((_, resource)) = self.pending.pop_front() {
+            resource.destroy(vk)?;
+        }
+
+        Ok(())
+    }
+}
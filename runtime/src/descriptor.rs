@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+
+use anyhow::Context;
+use ash::vk;
+
+use crate::Vk;
+
+#[derive(Default)]
+pub struct DescriptorSetLayoutBuilder {
+    bindings: Vec<vk::DescriptorSetLayoutBinding>,
+}
+
+impl DescriptorSetLayoutBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_binding(
+        &mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        stages: vk::ShaderStageFlags,
+        count: u32,
+    ) -> &mut Self {
+        self.bindings.push(
+            vk::DescriptorSetLayoutBinding::builder()
+                .binding(binding)
+                .descriptor_type(descriptor_type)
+                .descriptor_count(count)
+                .stage_flags(stages)
+                .build(),
+        );
+        self
+    }
+
+    pub fn bindings(&self) -> &[vk::DescriptorSetLayoutBinding] {
+        &self.bindings
+    }
+
+    pub fn build(&self, vk: &Vk) -> anyhow::Result<vk::DescriptorSetLayout> {
+        let create_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(&self.bindings)
+            .build();
+        unsafe {
+            vk.device()
+                .create_descriptor_set_layout(&create_info, None)
+                .context("failed to create descriptor set layout")
+        }
+    }
+}
+
+// sizes a pool so that `max_sets` sets of every added layout can be allocated
+#[derive(Default)]
+pub struct DescriptorPoolBuilder {
+    descriptor_counts: HashMap<vk::DescriptorType, u32>,
+}
+
+impl DescriptorPoolBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_layout(&mut self, layout: &DescriptorSetLayoutBuilder) -> &mut Self {
+        for binding in layout.bindings() {
+            *self
+                .descriptor_counts
+                .entry(binding.descriptor_type)
+                .or_default() += binding.descriptor_count;
+        }
+        self
+    }
+
+    pub fn build(&self, vk: &Vk, max_sets: u32) -> anyhow::Result<vk::DescriptorPool> {
+        let pool_sizes: Vec<_> = self
+            .descriptor_counts
+            .iter()
+            .map(|(ty, count)| vk::DescriptorPoolSize {
+                ty: *ty,
+                descriptor_count: count * max_sets,
+            })
+            .collect();
+        let create_info = vk::DescriptorPoolCreateInfo::builder()
+            .pool_sizes(&pool_sizes)
+            .max_sets(max_sets)
+            .build();
+        unsafe {
+            vk.device()
+                .create_descriptor_pool(&create_info, None)
+                .context("failed to create descriptor pool")
+        }
+    }
+}
+
+// hands out sets from a list of pools sized by `pool_builder`, a new pool is created once
+// the current one is exhausted
+pub struct DescriptorAllocator {
+    pool_builder: DescriptorPoolBuilder,
+    sets_per_pool: u32,
+    // the last pool is the one being allocated from
+    pools: Vec<vk::DescriptorPool>,
+    // reset pools that can be reused before creating new ones
+    free_pools: Vec<vk::DescriptorPool>,
+}
+
+impl DescriptorAllocator {
+    pub fn new(pool_builder: DescriptorPoolBuilder, sets_per_pool: u32) -> Self {
+        Self {
+            pool_builder,
+            sets_per_pool: sets_per_pool.max(1),
+            pools: vec![],
+            free_pools: vec![],
+        }
+    }
+
+    // `layout` should only use descriptor types that were added to the pool builder,
+    // otherwise no pool can ever satisfy the allocation
+    pub fn allocate(
+        &mut self,
+        vk: &Vk,
+        layout: vk::DescriptorSetLayout,
+    ) -> anyhow::Result<vk::DescriptorSet> {
+        if let Some(&pool) = self.pools.last() {
+            match allocate_descriptor_set(vk, pool, layout) {
+                Ok(set) => return Ok(set),
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => {}
+                Err(err) => return Err(err).context("failed to allocate descriptor set"),
+            }
+        }
+
+        let pool = match self.free_pools.pop() {
+            Some(pool) => pool,
+            None => self.pool_builder.build(vk, self.sets_per_pool)?,
+        };
+        self.pools.push(pool);
+        allocate_descriptor_set(vk, pool, layout)
+            .context("failed to allocate descriptor set from a new pool")
+    }
+
+    // frees every set handed out so far, none of them may still be in use by the gpu
+    pub fn reset(&mut self, vk: &Vk) -> anyhow::Result<()> {
+        for pool in self.pools.drain(..) {
+            unsafe {
+                vk.device()
+                    .reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())
+                    .context("failed to reset descriptor pool")?
+            };
+            self.free_pools.push(pool);
+        }
+
+        Ok(())
+    }
+
+    pub fn destroy(self, vk: &Vk) {
+        for pool in self.pools.into_iter().chain(self.free_pools) {
+            unsafe { vk.device().destroy_descriptor_pool(pool, None) };
+        }
+    }
+}
+
+fn allocate_descriptor_set(
+    vk: &Vk,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+) -> Result<vk::DescriptorSet, vk::Result> {
+    let set_layouts = [layout];
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(pool)
+        .set_layouts(&set_layouts)
+        .build();
+    unsafe { vk.device().allocate_descriptor_sets(&allocate_info) }.map(|sets| sets[0])
+}
+
+// allocates `count` sets with the same layout
+pub fn allocate_descriptor_sets(
+    vk: &Vk,
+    pool: vk::DescriptorPool,
+    layout: vk::DescriptorSetLayout,
+    count: u32,
+) -> anyhow::Result<Vec<vk::DescriptorSet>> {
+    let set_layouts = vec![layout; count as usize];
+    let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(pool)
+        .set_layouts(&set_layouts)
+        .build();
+    unsafe {
+        vk.device()
+            .allocate_descriptor_sets(&allocate_info)
+            .context("failed to allocate descriptor sets")
+    }
+}
+
+enum DescriptorInfo {
+    Buffer(vk::DescriptorBufferInfo),
+    Image(vk::DescriptorImageInfo),
+}
+
+struct PendingDescriptorWrite {
+    set: vk::DescriptorSet,
+    binding: u32,
+    descriptor_type: vk::DescriptorType,
+    info: DescriptorInfo,
+}
+
+// accumulates descriptor writes and copies across any number of sets and submits them in a
+// single vkUpdateDescriptorSets call
+#[derive(Default)]
+pub struct DescriptorUpdateBuilder {
+    writes: Vec<PendingDescriptorWrite>,
+    copies: Vec<vk::CopyDescriptorSet>,
+}
+
+impl DescriptorUpdateBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write_uniform_buffer(
+        &mut self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> &mut Self {
+        self.write_buffer(
+            set,
+            binding,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            buffer,
+            offset,
+            range,
+        )
+    }
+
+    pub fn write_storage_buffer(
+        &mut self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> &mut Self {
+        self.write_buffer(
+            set,
+            binding,
+            vk::DescriptorType::STORAGE_BUFFER,
+            buffer,
+            offset,
+            range,
+        )
+    }
+
+    pub fn write_combined_image_sampler(
+        &mut self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        view: vk::ImageView,
+        sampler: vk::Sampler,
+        layout: vk::ImageLayout,
+    ) -> &mut Self {
+        self.write_image(
+            set,
+            binding,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            view,
+            sampler,
+            layout,
+        )
+    }
+
+    pub fn write_sampled_image(
+        &mut self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        view: vk::ImageView,
+        layout: vk::ImageLayout,
+    ) -> &mut Self {
+        self.write_image(
+            set,
+            binding,
+            vk::DescriptorType::SAMPLED_IMAGE,
+            view,
+            vk::Sampler::null(),
+            layout,
+        )
+    }
+
+    // storage images are accessed in GENERAL layout
+    pub fn write_storage_image(
+        &mut self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        view: vk::ImageView,
+    ) -> &mut Self {
+        self.write_image(
+            set,
+            binding,
+            vk::DescriptorType::STORAGE_IMAGE,
+            view,
+            vk::Sampler::null(),
+            vk::ImageLayout::GENERAL,
+        )
+    }
+
+    pub fn write_sampler(
+        &mut self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        sampler: vk::Sampler,
+    ) -> &mut Self {
+        self.write_image(
+            set,
+            binding,
+            vk::DescriptorType::SAMPLER,
+            vk::ImageView::null(),
+            sampler,
+            vk::ImageLayout::UNDEFINED,
+        )
+    }
+
+    // copies `count` descriptors starting at array element 0, copies are applied after
+    // all writes of the same flush
+    pub fn copy(
+        &mut self,
+        src_set: vk::DescriptorSet,
+        src_binding: u32,
+        dst_set: vk::DescriptorSet,
+        dst_binding: u32,
+        count: u32,
+    ) -> &mut Self {
+        self.copies.push(
+            vk::CopyDescriptorSet::builder()
+                .src_set(src_set)
+                .src_binding(src_binding)
+                .dst_set(dst_set)
+                .dst_binding(dst_binding)
+                .descriptor_count(count)
+                .build(),
+        );
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty() && self.copies.is_empty()
+    }
+
+    // the builder is empty afterwards and can be reused
+    pub fn flush(&mut self, vk: &Vk) {
+        if self.is_empty() {
+            return;
+        }
+
+        // the infos are borrowed by the writes, they stay in `self.writes` until the update is done
+        let writes: Vec<_> = self
+            .writes
+            .iter()
+            .map(|write| {
+                let builder = vk::WriteDescriptorSet::builder()
+                    .dst_set(write.set)
+                    .dst_binding(write.binding)
+                    .descriptor_type(write.descriptor_type);
+                match &write.info {
+                    DescriptorInfo::Buffer(info) => {
+                        builder.buffer_info(std::slice::from_ref(info)).build()
+                    }
+                    DescriptorInfo::Image(info) => {
+                        builder.image_info(std::slice::from_ref(info)).build()
+                    }
+                }
+            })
+            .collect();
+        unsafe { vk.device().update_descriptor_sets(&writes, &self.copies) };
+
+        self.writes.clear();
+        self.copies.clear();
+    }
+
+    fn write_image(
+        &mut self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        view: vk::ImageView,
+        sampler: vk::Sampler,
+        layout: vk::ImageLayout,
+    ) -> &mut Self {
+        self.writes.push(PendingDescriptorWrite {
+            set,
+            binding,
+            descriptor_type,
+            info: DescriptorInfo::Image(vk::DescriptorImageInfo {
+                sampler,
+                image_view: view,
+                image_layout: layout,
+            }),
+        });
+        self
+    }
+
+    fn write_buffer(
+        &mut self,
+        set: vk::DescriptorSet,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        buffer: vk::Buffer,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> &mut Self {
+        self.writes.push(PendingDescriptorWrite {
+            set,
+            binding,
+            descriptor_type,
+            info: DescriptorInfo::Buffer(vk::DescriptorBufferInfo {
+                buffer,
+                offset,
+                range,
+            }),
+        });
+        self
+    }
+}
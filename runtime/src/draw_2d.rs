@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::mem::size_of;
+
+use anyhow::Context;
+use ash::vk::{
+    BlendFactor, BlendOp, Buffer, BufferUsageFlags, ColorComponentFlags, CommandBuffer, Extent2D,
+    Format, Offset2D, Pipeline, PipelineBindPoint, PipelineColorBlendAttachmentState,
+    PipelineLayout, PushConstantRange, Rect2D, SampleCountFlags, ShaderStageFlags,
+    VertexInputAttributeDescription, VertexInputBindingDescription, VertexInputRate, Viewport,
+};
+use gpu_allocator::vulkan::Allocation;
+use gpu_allocator::MemoryLocation;
+
+use crate::pipeline::GraphicsPipelineBuilder;
+use crate::vk_utils::push_constants;
+use crate::Vk;
+
+const VERT_SPV: &[u8] = include_bytes!("../shaders/draw_2d.vert.spv");
+const FRAG_SPV: &[u8] = include_bytes!("../shaders/draw_2d.frag.spv");
+
+const CIRCLE_SEGMENTS: usize = 32;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Vertex2d {
+    position: [f32; 2],
+    color: [f32; 4],
+}
+
+struct VertexBuffer {
+    buffer: Buffer,
+    allocation: Allocation,
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct FrameVertices {
+    vertex_buffer: Option<VertexBuffer>,
+    // vertices written by earlier flushes of the frame, the next one appends after them
+    used: usize,
+    // outgrown during the frame, earlier draws of the frame may still read them
+    retired: Vec<VertexBuffer>,
+}
+
+// the attachments of the rendering scope the primitives are flushed into, the pipeline
+// has to be built for the same ones
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Draw2dTarget {
+    pub color_format: Format,
+    // UNDEFINED without a depth attachment
+    pub depth_format: Format,
+    pub samples: SampleCountFlags,
+    pub view_mask: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Draw2dPipeline {
+    pipeline: Pipeline,
+    layout: PipelineLayout,
+}
+
+// immediate mode 2d primitives in pixel coordinates, accumulated during the frame and
+// drawn as a single triangle list on flush
+pub(crate) struct Draw2d {
+    vertices: Vec<Vertex2d>,
+    // kept for every target flushed into so far, a pipeline may still be used by a frame
+    // in flight or earlier in the current command buffer
+    pipelines: HashMap<Draw2dTarget, Draw2dPipeline>,
+    // one host visible buffer per frame in flight
+    frames: Vec<FrameVertices>,
+}
+
+impl Draw2d {
+    pub fn new(frames_in_flight: usize) -> Self {
+        Self {
+            vertices: vec![],
+            pipelines: HashMap::new(),
+            frames: (0..frames_in_flight)
+                .map(|_| FrameVertices::default())
+                .collect(),
+        }
+    }
+
+    // the gpu must be done with the previous frame of `frame_slot`, primitives that weren't
+    // flushed during the last frame are dropped
+    pub fn begin_frame(&mut self, vk: &mut Vk, frame_slot: usize) -> anyhow::Result<()> {
+        self.vertices.clear();
+        let frame = &mut self.frames[frame_slot];
+        frame.used = 0;
+        for vertex_buffer in frame.retired.drain(..) {
+            vk.destroy_buffer(vertex_buffer.buffer, vertex_buffer.allocation)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn rect(&mut self, min: [f32; 2], max: [f32; 2], color: [f32; 4]) {
+        let top_right = [max[0], min[1]];
+        let bottom_left = [min[0], max[1]];
+        self.triangle(min, top_right, max, color);
+        self.triangle(min, max, bottom_left, color);
+    }
+
+    // drawn as a 1 pixel wide quad
+    pub fn line(&mut self, a: [f32; 2], b: [f32; 2], color: [f32; 4]) {
+        let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+        let length = (dx * dx + dy * dy).sqrt();
+        if length == 0.0 {
+            return;
+        }
+        let (nx, ny) = (-dy / length * 0.5, dx / length * 0.5);
+
+        let a0 = [a[0] + nx, a[1] + ny];
+        let a1 = [a[0] - nx, a[1] - ny];
+        let b0 = [b[0] + nx, b[1] + ny];
+        let b1 = [b[0] - nx, b[1] - ny];
+        self.triangle(a0, b0, b1, color);
+        self.triangle(a0, b1, a1, color);
+    }
+
+    pub fn circle(&mut self, center: [f32; 2], radius: f32, color: [f32; 4]) {
+        let point = |i: usize| {
+            let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+            [
+                center[0] + angle.cos() * radius,
+                center[1] + angle.sin() * radius,
+            ]
+        };
+
+        for i in 0..CIRCLE_SEGMENTS {
+            self.triangle(center, point(i), point(i + 1), color);
+        }
+    }
+
+    // records the accumulated primitives, must be called inside a dynamic rendering scope
+    // whose attachments are described by `target` and have `extent`
+    pub fn flush(
+        &mut self,
+        vk: &mut Vk,
+        cmd: CommandBuffer,
+        frame_slot: usize,
+        target: Draw2dTarget,
+        extent: Extent2D,
+    ) -> anyhow::Result<()> {
+        if self.vertices.is_empty() {
+            return Ok(());
+        }
+
+        let pipeline = self.ensure_pipeline(vk, target)?;
+        self.ensure_vertex_buffer(vk, frame_slot)?;
+
+        let frame = &mut self.frames[frame_slot];
+        let vertex_buffer = frame.vertex_buffer.as_ref().unwrap();
+        let first_vertex = frame.used;
+        frame.used += self.vertices.len();
+        let mapped_ptr = vertex_buffer
+            .allocation
+            .mapped_ptr()
+            .context("draw 2d vertex buffer is not host visible")?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                self.vertices.as_ptr(),
+                mapped_ptr.as_ptr().cast::<Vertex2d>().add(first_vertex),
+                self.vertices.len(),
+            );
+        }
+
+        let device = vk.device();
+        let viewport = Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        let scissor = Rect2D {
+            offset: Offset2D::default(),
+            extent,
+        };
+        let screen_size = [extent.width as f32, extent.height as f32];
+
+        unsafe {
+            device.cmd_bind_pipeline(cmd, PipelineBindPoint::GRAPHICS, pipeline.pipeline);
+            device.cmd_set_viewport(cmd, 0, &[viewport]);
+            device.cmd_set_scissor(cmd, 0, &[scissor]);
+        }
+        push_constants(
+            vk,
+            cmd,
+            pipeline.layout,
+            ShaderStageFlags::VERTEX,
+            0,
+            &screen_size,
+        );
+        unsafe {
+            let offset = (first_vertex * size_of::<Vertex2d>()) as u64;
+            device.cmd_bind_vertex_buffers(cmd, 0, &[vertex_buffer.buffer], &[offset]);
+            device.cmd_draw(cmd, self.vertices.len() as u32, 1, 0, 0);
+        }
+
+        self.vertices.clear();
+
+        Ok(())
+    }
+
+    pub fn destroy(&mut self, vk: &mut Vk) -> anyhow::Result<()> {
+        for (_, pipeline) in self.pipelines.drain() {
+            unsafe { vk.device().destroy_pipeline(pipeline.pipeline, None) };
+            vk.destroy_pipeline_layout(pipeline.layout);
+        }
+        for frame in self.frames.iter_mut() {
+            for vertex_buffer in frame
+                .vertex_buffer
+                .take()
+                .into_iter()
+                .chain(frame.retired.drain(..))
+            {
+                vk.destroy_buffer(vertex_buffer.buffer, vertex_buffer.allocation)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn triangle(&mut self, a: [f32; 2], b: [f32; 2], c: [f32; 2], color: [f32; 4]) {
+        self.vertices
+            .extend([a, b, c].map(|position| Vertex2d { position, color }));
+    }
+
+    fn ensure_pipeline(&mut self, vk: &Vk, target: Draw2dTarget) -> anyhow::Result<Draw2dPipeline> {
+        if let Some(pipeline) = self.pipelines.get(&target) {
+            return Ok(*pipeline);
+        }
+
+        let vertex_shader = vk.load_shader_module_from_bytes(VERT_SPV)?;
+        let fragment_shader = match vk.load_shader_module_from_bytes(FRAG_SPV) {
+            Ok(module) => module,
+            Err(err) => {
+                vk.destroy_shader_module(vertex_shader);
+                return Err(err);
+            }
+        };
+
+        let entry = c"main";
+        let result = GraphicsPipelineBuilder::new()
+            .vertex_shader(vertex_shader, entry)
+            .fragment_shader(fragment_shader, entry)
+            .vertex_input(
+                &[VertexInputBindingDescription {
+                    binding: 0,
+                    stride: size_of::<Vertex2d>() as u32,
+                    input_rate: VertexInputRate::VERTEX,
+                }],
+                &[
+                    VertexInputAttributeDescription {
+                        location: 0,
+                        binding: 0,
+                        format: Format::R32G32_SFLOAT,
+                        offset: 0,
+                    },
+                    VertexInputAttributeDescription {
+                        location: 1,
+                        binding: 0,
+                        format: Format::R32G32B32A32_SFLOAT,
+                        offset: size_of::<[f32; 2]>() as u32,
+                    },
+                ],
+            )
+            .color_blend(&[PipelineColorBlendAttachmentState::builder()
+                .blend_enable(true)
+                .src_color_blend_factor(BlendFactor::SRC_ALPHA)
+                .dst_color_blend_factor(BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .color_blend_op(BlendOp::ADD)
+                .src_alpha_blend_factor(BlendFactor::ONE)
+                .dst_alpha_blend_factor(BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .alpha_blend_op(BlendOp::ADD)
+                .color_write_mask(ColorComponentFlags::RGBA)
+                .build()])
+            .layout(
+                &[],
+                &[PushConstantRange {
+                    stage_flags: ShaderStageFlags::VERTEX,
+                    offset: 0,
+                    size: size_of::<[f32; 2]>() as u32,
+                }],
+            )
+            .render_format(&[target.color_format], target.depth_format)
+            .rasterization_samples(target.samples)
+            .view_mask(target.view_mask)
+            .build(vk);
+
+        vk.destroy_shader_module(vertex_shader);
+        vk.destroy_shader_module(fragment_shader);
+
+        let (pipeline, layout) = result?;
+        let pipeline = Draw2dPipeline { pipeline, layout };
+        self.pipelines.insert(target, pipeline);
+
+        Ok(pipeline)
+    }
+
+    fn ensure_vertex_buffer(&mut self, vk: &mut Vk, frame_slot: usize) -> anyhow::Result<()> {
+        let frame = &mut self.frames[frame_slot];
+        let required = frame.used + self.vertices.len();
+        if let Some(vertex_buffer) = &frame.vertex_buffer {
+            if vertex_buffer.capacity >= required {
+                return Ok(());
+            }
+        }
+
+        if let Some(vertex_buffer) = frame.vertex_buffer.take() {
+            frame.retired.push(vertex_buffer);
+        }
+
+        // grow geometrically to avoid reallocating every frame, sized for everything
+        // flushed this frame so the next frame fits without growing again
+        let capacity = required.next_power_of_two();
+        let (buffer, allocation) = vk.allocate_buffer(
+            (capacity * size_of::<Vertex2d>()) as u64,
+            BufferUsageFlags::VERTEX_BUFFER,
+            MemoryLocation::CpuToGpu,
+        )?;
+        frame.vertex_buffer = Some(VertexBuffer {
+            buffer,
+            allocation,
+            capacity,
+        });
+        frame.used = 0;
+
+        Ok(())
+    }
+}
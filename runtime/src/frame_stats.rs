@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+// rolling window of frame loop durations, see `AppContext::fps`
+#[derive(Clone, Debug)]
+pub struct FrameStats {
+    // ring buffer, `next` is the oldest entry once it's full
+    frame_times: Vec<Duration>,
+    next: usize,
+    capacity: usize,
+}
+
+impl FrameStats {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            frame_times: Vec::with_capacity(capacity),
+            next: 0,
+            capacity,
+        }
+    }
+
+    pub fn record(&mut self, frame_time: Duration) {
+        if self.frame_times.len() < self.capacity {
+            self.frame_times.push(frame_time);
+        } else {
+            self.frame_times[self.next] = frame_time;
+        }
+        self.next = (self.next + 1) % self.capacity;
+    }
+
+    // number of recorded frames, at most the capacity
+    pub fn len(&self) -> usize {
+        self.frame_times.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frame_times.is_empty()
+    }
+
+    // average over the recorded frames, 0 without any
+    pub fn frame_time_ms(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        let total: Duration = self.frame_times.iter().sum();
+        total.as_secs_f32() * 1000.0 / self.frame_times.len() as f32
+    }
+
+    pub fn fps(&self) -> f32 {
+        let frame_time_ms = self.frame_time_ms();
+        if frame_time_ms > 0.0 {
+            1000.0 / frame_time_ms
+        } else {
+            0.0
+        }
+    }
+
+    // nearest rank, `percentile` in 0..=100, e.g. 99 for the time 99% of the frames stay under
+    pub fn percentile_ms(&self, percentile: f32) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        let mut sorted = self.frame_times.clone();
+        sorted.sort_unstable();
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * sorted.len() as f32).ceil() as usize;
+        sorted[rank.saturating_sub(1)].as_secs_f32() * 1000.0
+    }
+
+    pub fn clear(&mut self) {
+        self.frame_times.clear();
+        self.next = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn oldest_frames_are_overwritten_once_full() {
+        let mut stats = FrameStats::new(3);
+        for frame_time in [10, 20, 30, 40, 50] {
+            stats.record(ms(frame_time));
+        }
+        assert_eq!(stats.len(), 3);
+        // 30, 40 and 50 are left
+        assert!((stats.frame_time_ms() - 40.0).abs() < 1e-3);
+        assert!((stats.percentile_ms(0.0) - 30.0).abs() < 1e-3);
+        assert!((stats.percentile_ms(100.0) - 50.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn empty_stats_report_zero() {
+        let stats = FrameStats::new(4);
+        assert!(stats.is_empty());
+        assert_eq!(stats.frame_time_ms(), 0.0);
+        assert_eq!(stats.fps(), 0.0);
+        assert_eq!(stats.percentile_ms(99.0), 0.0);
+    }
+
+    #[test]
+    fn a_single_frame_is_every_percentile() {
+        let mut stats = FrameStats::new(4);
+        stats.record(ms(16));
+        for percentile in [0.0, 50.0, 99.0, 100.0] {
+            assert!((stats.percentile_ms(percentile) - 16.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank_when_full() {
+        let mut stats = FrameStats::new(100);
+        // recorded out of order to cover the sort
+        for frame_time in (1..=100).rev() {
+            stats.record(ms(frame_time));
+        }
+        assert_eq!(stats.len(), 100);
+        assert!((stats.percentile_ms(50.0) - 50.0).abs() < 1e-3);
+        assert!((stats.percentile_ms(99.0) - 99.0).abs() < 1e-3);
+        assert!((stats.percentile_ms(100.0) - 100.0).abs() < 1e-3);
+        assert!((stats.fps() - 1000.0 / 50.5).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,271 @@
+use std::fmt::Write;
+
+use anyhow::{bail, Context};
+use ash::vk::{CommandBuffer, PipelineStageFlags, QueryPool, QueryResultFlags};
+use ash::Device;
+
+use crate::vk_utils::{create_timestamp_pool, timestamp_valid_bits};
+use crate::Vk;
+
+const MAX_TIMESTAMPS_PER_FRAME: u32 = 256;
+
+#[derive(Clone, Debug)]
+pub struct TimelineRegion {
+    pub name: String,
+    // nesting level, 0 for top level regions
+    pub depth: u32,
+    // relative to the first timestamp of the frame
+    pub begin_ns: f64,
+    pub end_ns: f64,
+}
+
+impl TimelineRegion {
+    pub fn duration_ms(&self) -> f64 {
+        (self.end_ns - self.begin_ns) / 1_000_000.0
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FrameTimeline {
+    pub frame_idx: u64,
+    pub regions: Vec<TimelineRegion>,
+}
+
+impl FrameTimeline {
+    // chrome://tracing and perfetto compatible, nesting depth is used as the thread id
+    pub fn to_chrome_trace_json(&self) -> String {
+        let mut json = String::from("{\"traceEvents\":[");
+        for (idx, region) in self.regions.iter().enumerate() {
+            if idx > 0 {
+                json.push(',');
+            }
+            let _ = write!(
+                json,
+                "{{\"name\":\"{}\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":0,\"tid\":{}}}",
+                escape_json(&region.name),
+                region.begin_ns / 1000.0,
+                (region.end_ns - region.begin_ns) / 1000.0,
+                region.depth
+            );
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+struct PendingRegion {
+    name: String,
+    depth: u32,
+    begin_query: Option<u32>,
+    end_query: Option<u32>,
+}
+
+#[derive(Default)]
+struct FrameSlot {
+    frame_idx: Option<u64>,
+    regions: Vec<PendingRegion>,
+    // indices into `regions` that haven't been ended yet
+    open: Vec<usize>,
+    query_count: u32,
+}
+
+// timestamp queries around labeled regions, one range of queries per frame in flight
+// a slot is read back when it is reused, by then its frame latency fence has been waited on
+pub(crate) struct GpuTimeline {
+    query_pool: QueryPool,
+    // nanoseconds per timestamp tick
+    timestamp_period: f64,
+    timestamp_mask: u64,
+    slots: Vec<FrameSlot>,
+    last_completed: Option<FrameTimeline>,
+}
+
+impl GpuTimeline {
+    // None if the graphics queue doesn't support timestamps
+    pub fn new(vk: &Vk, frames_in_flight: usize) -> anyhow::Result<Option<Self>> {
+        let timestamp_valid_bits = timestamp_valid_bits(vk);
+        if timestamp_valid_bits == 0 {
+            log::warn!("graphics queue doesn't support timestamps, gpu timeline is disabled");
+            return Ok(None);
+        }
+        let properties = unsafe {
+            vk.instance()
+                .get_physical_device_properties(*vk.physical_device())
+        };
+
+        let query_pool =
+            create_timestamp_pool(vk, MAX_TIMESTAMPS_PER_FRAME * frames_in_flight as u32)?;
+
+        Ok(Some(Self {
+            query_pool,
+            timestamp_period: properties.limits.timestamp_period as f64,
+            timestamp_mask: u64::MAX >> (64 - timestamp_valid_bits),
+            slots: (0..frames_in_flight)
+                .map(|_| FrameSlot::default())
+                .collect(),
+            last_completed: None,
+        }))
+    }
+
+    pub fn last_completed(&self) -> Option<&FrameTimeline> {
+        self.last_completed.as_ref()
+    }
+
+    // the first region of a frame resets the frame's queries, which is only valid
+    // outside of a rendering scope
+    pub fn begin_region(
+        &mut self,
+        device: &Device,
+        cmd: CommandBuffer,
+        frame_idx: u64,
+        name: &str,
+    ) -> anyhow::Result<()> {
+        let slot_idx = self.slot_idx(frame_idx);
+        if self.slots[slot_idx].frame_idx != Some(frame_idx) {
+            self.collect(device, slot_idx)?;
+            unsafe {
+                device.cmd_reset_query_pool(
+                    cmd,
+                    self.query_pool,
+                    slot_idx as u32 * MAX_TIMESTAMPS_PER_FRAME,
+                    MAX_TIMESTAMPS_PER_FRAME,
+                )
+            };
+            self.slots[slot_idx] = FrameSlot {
+                frame_idx: Some(frame_idx),
+                ..Default::default()
+            };
+        }
+
+        let begin_query =
+            self.write_timestamp(device, cmd, slot_idx, PipelineStageFlags::TOP_OF_PIPE);
+        let slot = &mut self.slots[slot_idx];
+        slot.regions.push(PendingRegion {
+            name: name.to_string(),
+            depth: slot.open.len() as u32,
+            begin_query,
+            end_query: None,
+        });
+        slot.open.push(slot.regions.len() - 1);
+
+        Ok(())
+    }
+
+    pub fn end_region(
+        &mut self,
+        device: &Device,
+        cmd: CommandBuffer,
+        frame_idx: u64,
+    ) -> anyhow::Result<()> {
+        let slot_idx = self.slot_idx(frame_idx);
+        if self.slots[slot_idx].frame_idx != Some(frame_idx) {
+            bail!("no gpu timeline region was begun this frame");
+        }
+        let Some(region_idx) = self.slots[slot_idx].open.pop() else {
+            bail!("no open gpu timeline region to end");
+        };
+
+        let end_query =
+            self.write_timestamp(device, cmd, slot_idx, PipelineStageFlags::BOTTOM_OF_PIPE);
+        self.slots[slot_idx].regions[region_idx].end_query = end_query;
+
+        Ok(())
+    }
+
+    pub fn destroy(&mut self, device: &Device) {
+        unsafe { device.destroy_query_pool(self.query_pool, None) };
+    }
+
+    fn slot_idx(&self, frame_idx: u64) -> usize {
+        (frame_idx % self.slots.len() as u64) as usize
+    }
+
+    // None once the frame ran out of queries, the region is then dropped from the timeline
+    fn write_timestamp(
+        &mut self,
+        device: &Device,
+        cmd: CommandBuffer,
+        slot_idx: usize,
+        stage: PipelineStageFlags,
+    ) -> Option<u32> {
+        let slot = &mut self.slots[slot_idx];
+        if slot.query_count == MAX_TIMESTAMPS_PER_FRAME {
+            log::warn!("more than {MAX_TIMESTAMPS_PER_FRAME} gpu timestamps in a frame");
+            return None;
+        }
+        let query = slot_idx as u32 * MAX_TIMESTAMPS_PER_FRAME + slot.query_count;
+        slot.query_count += 1;
+        unsafe { device.cmd_write_timestamp(cmd, stage, self.query_pool, query) };
+        Some(query)
+    }
+
+    fn collect(&mut self, device: &Device, slot_idx: usize) -> anyhow::Result<()> {
+        let slot = &self.slots[slot_idx];
+        let Some(frame_idx) = slot.frame_idx else {
+            return Ok(());
+        };
+        if slot.query_count == 0 {
+            return Ok(());
+        }
+
+        let first_query = slot_idx as u32 * MAX_TIMESTAMPS_PER_FRAME;
+        let mut timestamps = vec![0u64; slot.query_count as usize];
+        let results = unsafe {
+            device.get_query_pool_results(
+                self.query_pool,
+                first_query,
+                slot.query_count,
+                &mut timestamps,
+                QueryResultFlags::TYPE_64,
+            )
+        };
+        match results {
+            Ok(()) => {}
+            // the frame was never submitted or its fence wasn't waited on, skip it
+            Err(ash::vk::Result::NOT_READY) => return Ok(()),
+            Err(err) => return Err(err).context("failed to read gpu timestamps"),
+        }
+        let timestamp =
+            |query: u32| timestamps[(query - first_query) as usize] & self.timestamp_mask;
+
+        let regions: Vec<_> = slot
+            .regions
+            .iter()
+            .filter_map(|region| Some((region, region.begin_query?, region.end_query?)))
+            .collect();
+        let Some(frame_begin) = regions.iter().map(|(_, begin, _)| timestamp(*begin)).min() else {
+            return Ok(());
+        };
+        let to_ns = |ticks: u64| ticks.wrapping_sub(frame_begin) as f64 * self.timestamp_period;
+
+        self.last_completed = Some(FrameTimeline {
+            frame_idx,
+            regions: regions
+                .into_iter()
+                .map(|(region, begin, end)| TimelineRegion {
+                    name: region.name.clone(),
+                    depth: region.depth,
+                    begin_ns: to_ns(timestamp(begin)),
+                    end_ns: to_ns(timestamp(end)),
+                })
+                .collect(),
+        });
+
+        Ok(())
+    }
+}
+
+fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use glfw::{Action, Key, Modifiers, MouseButton, WindowEvent};
+
+// one event per line: `<frame> <seconds> <kind> <args...>`, frame and time are relative to
+// the start of the recording
+
+struct RecordedEvent {
+    frame: u64,
+    time: Duration,
+    event: WindowEvent,
+}
+
+pub(crate) struct InputRecorder {
+    writer: BufWriter<File>,
+    start_frame: u64,
+    start_time: Instant,
+}
+
+impl InputRecorder {
+    pub fn new(path: &Path, frame_idx: u64) -> anyhow::Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("failed to create input recording {}", path.display()))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+            start_frame: frame_idx,
+            start_time: Instant::now(),
+        })
+    }
+
+    // events that are driven by the window system rather than the user are not recorded
+    pub fn record(&mut self, frame_idx: u64, event: &WindowEvent) -> anyhow::Result<()> {
+        let Some(args) = encode_event(event) else {
+            return Ok(());
+        };
+        writeln!(
+            self.writer,
+            "{} {} {}",
+            frame_idx - self.start_frame,
+            self.start_time.elapsed().as_secs_f64(),
+            args
+        )
+        .context("failed to write input recording")
+    }
+
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        self.writer
+            .flush()
+            .context("failed to flush input recording")
+    }
+}
+
+pub(crate) struct InputReplay {
+    events: VecDeque<RecordedEvent>,
+    start_frame: u64,
+    start_time: Instant,
+}
+
+impl InputReplay {
+    pub fn new(path: &Path, frame_idx: u64) -> anyhow::Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("failed to open input recording {}", path.display()))?;
+        let mut events = VecDeque::new();
+        for (line_idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.context("failed to read input recording")?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event = parse_line(&line)
+                .with_context(|| format!("invalid input recording at line {}", line_idx + 1))?;
+            events.push_back(event);
+        }
+
+        Ok(Self {
+            events,
+            start_frame: frame_idx,
+            start_time: Instant::now(),
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    // events are released on the frame they were recorded on so the app sees the same
+    // sequence, if playback runs ahead of the recording it sleeps to match the original pacing
+    pub fn due_events(&mut self, frame_idx: u64) -> Vec<WindowEvent> {
+        let frame = frame_idx - self.start_frame;
+        let mut due = vec![];
+        while let Some(recorded) = self.events.front() {
+            if recorded.frame > frame {
+                break;
+            }
+            let elapsed = self.start_time.elapsed();
+            if recorded.time > elapsed {
+                std::thread::sleep(recorded.time - elapsed);
+            }
+            due.push(self.events.pop_front().unwrap().event);
+        }
+        due
+    }
+}
+
+// whether the event is user input, real input of this kind is ignored during replay
+pub(crate) fn is_recordable(event: &WindowEvent) -> bool {
+    encode_event(event).is_some()
+}
+
+fn encode_event(event: &WindowEvent) -> Option<String> {
+    let encoded = match event {
+        WindowEvent::Key(key, scancode, action, modifiers) => format!(
+            "key {} {} {} {}",
+            *key as i32,
+            scancode,
+            *action as i32,
+            modifiers.bits()
+        ),
+        WindowEvent::Char(c) => format!("char {}", *c as u32),
+        WindowEvent::CharModifiers(c, modifiers) => {
+            format!("char_modifiers {} {}", *c as u32, modifiers.bits())
+        }
+        WindowEvent::MouseButton(button, action, modifiers) => format!(
+            "mouse_button {} {} {}",
+            *button as i32,
+            *action as i32,
+            modifiers.bits()
+        ),
+        WindowEvent::CursorPos(x, y) => format!("cursor_pos {x} {y}"),
+        WindowEvent::CursorEnter(entered) => format!("cursor_enter {entered}"),
+        WindowEvent::Scroll(x, y) => format!("scroll {x} {y}"),
+        _ => return None,
+    };
+    Some(encoded)
+}
+
+fn parse_line(line: &str) -> anyhow::Result<RecordedEvent> {
+    let mut parts = line.split_whitespace();
+    let mut next = |name: &str| parts.next().with_context(|| format!("missing {name}"));
+
+    let frame = next("frame")?.parse().context("invalid frame")?;
+    let time: f64 = next("time")?.parse().context("invalid time")?;
+    let time = Duration::try_from_secs_f64(time).context("invalid time")?;
+    let kind = next("event kind")?;
+
+    let event = match kind {
+        "key" => WindowEvent::Key(
+            key_from_i32(next("key")?.parse()?)?,
+            next("scancode")?.parse()?,
+            action_from_i32(next("action")?.parse()?)?,
+            modifiers_from_bits(next("modifiers")?.parse()?)?,
+        ),
+        "char" => WindowEvent::Char(char_from_u32(next("char")?.parse()?)?),
+        "char_modifiers" => WindowEvent::CharModifiers(
+            char_from_u32(next("char")?.parse()?)?,
+            modifiers_from_bits(next("modifiers")?.parse()?)?,
+        ),
+        "mouse_button" => WindowEvent::MouseButton(
+            MouseButton::from_i32(next("button")?.parse()?).context("invalid mouse button")?,
+            action_from_i32(next("action")?.parse()?)?,
+            modifiers_from_bits(next("modifiers")?.parse()?)?,
+        ),
+        "cursor_pos" => WindowEvent::CursorPos(next("x")?.parse()?, next("y")?.parse()?),
+        "cursor_enter" => WindowEvent::CursorEnter(next("entered")?.parse()?),
+        "scroll" => WindowEvent::Scroll(next("x")?.parse()?, next("y")?.parse()?),
+        _ => bail!("unknown event kind {kind}"),
+    };
+
+    Ok(RecordedEvent { frame, time, event })
+}
+
+fn key_from_i32(code: i32) -> anyhow::Result<Key> {
+    // the ranges of key codes defined by glfw, see glfw3.h
+    let valid = matches!(
+        code,
+        -1 | 32
+            | 39
+            | 44..=57
+            | 59
+            | 61
+            | 65..=93
+            | 96
+            | 161
+            | 162
+            | 256..=269
+            | 280..=284
+            | 290..=314
+            | 320..=336
+            | 340..=348
+    );
+    if !valid {
+        bail!("invalid key code {code}");
+    }
+    // Key is a repr(i32) enum and the code was checked against its discriminants
+    Ok(unsafe { std::mem::transmute::<i32, Key>(code) })
+}
+
+fn action_from_i32(action: i32) -> anyhow::Result<Action> {
+    match action {
+        0 => Ok(Action::Release),
+        1 => Ok(Action::Press),
+        2 => Ok(Action::Repeat),
+        _ => bail!("invalid action {action}"),
+    }
+}
+
+fn modifiers_from_bits(bits: i32) -> anyhow::Result<Modifiers> {
+    Modifiers::from_bits(bits).with_context(|| format!("invalid modifiers {bits}"))
+}
+
+fn char_from_u32(c: u32) -> anyhow::Result<char> {
+    char::from_u32(c).with_context(|| format!("invalid char {c}"))
+}
@@ -1,34 +1,115 @@
-use std::ffi::{CStr, CString};
+use std::collections::HashMap;
+use std::ffi::CString;
 use std::mem::ManuallyDrop;
 use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
+#[cfg(feature = "validation_layers")]
+use ash::vk::DebugUtilsMessengerEXT;
 use ash::vk::{
-    ColorSpaceKHR, ComponentMapping, CompositeAlphaFlagsKHR, Extent2D, Fence, Format, Image,
-    ImageAspectFlags, ImageSubresourceRange, ImageUsageFlags, ImageView, ImageViewCreateInfo,
-    ImageViewType, PhysicalDevice, PresentModeKHR, Queue, Semaphore, SurfaceKHR,
-    SurfaceTransformFlagsKHR, SwapchainCreateInfoKHR, SwapchainKHR,
+    AccessFlags, AttachmentLoadOp, AttachmentStoreOp, BindSparseInfo, Buffer, BufferCopy,
+    BufferCreateInfo, BufferUsageFlags, ClearColorValue, ClearValue, ColorSpaceKHR, CommandBuffer,
+    CommandBufferAllocateInfo, CommandBufferBeginInfo, CommandBufferLevel, CommandBufferResetFlags,
+    CommandBufferUsageFlags, CommandPool, CommandPoolCreateFlags, CommandPoolCreateInfo,
+    ComponentMapping, CompositeAlphaFlagsKHR, DependencyFlags, Extent2D, Extent3D, Fence,
+    FenceCreateFlags, FenceCreateInfo, Filter, Format, FormatFeatureFlags, Image, ImageAspectFlags,
+    ImageBlit, ImageCreateFlags, ImageCreateInfo, ImageFormatListCreateInfo, ImageLayout,
+    ImageMemoryBarrier, ImageSubresourceLayers, ImageSubresourceRange, ImageTiling, ImageType,
+    ImageUsageFlags, ImageView, ImageViewCreateInfo, ImageViewType, Offset2D, Offset3D,
+    PhysicalDevice, PhysicalDeviceFeatures, PipelineCache, PipelineCacheCreateInfo, PipelineLayout,
+    PipelineStageFlags, PresentGravityFlagsEXT, PresentIdKHR, PresentInfoKHR, PresentModeKHR,
+    PresentScalingFlagsEXT, PushConstantRange, QueryPool, Queue, Rect2D,
+    RenderingAttachmentInfoKHR, RenderingInfoKHR, ResolveModeFlags, SampleCountFlags, Sampler,
+    Semaphore, ShaderModule, ShaderStageFlags, SharingMode, SubmitInfo, SurfaceKHR,
+    SurfaceTransformFlagsKHR, SwapchainCreateFlagsKHR, SwapchainCreateInfoKHR, SwapchainKHR,
+    SwapchainPresentScalingCreateInfoEXT, QUEUE_FAMILY_IGNORED,
 };
 use ash::{Device, Entry, Instance};
 use glfw::ClientApiHint::NoApi;
 use glfw::{Action, Glfw, Key, Window, WindowEvent, WindowHint, WindowMode};
-use raw_window_handle::HasRawDisplayHandle;
+use gpu_allocator::vulkan::{
+    Allocation, AllocationCreateDesc, AllocationScheme, Allocator, AllocatorCreateDesc,
+};
+use gpu_allocator::MemoryLocation;
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
 
+use crate::assets::AssetResolver;
+use crate::color::encode_for_swapchain;
+use crate::deferred_destroy::{DeferredDestroyQueue, DeferredResource};
+use crate::draw_2d::{Draw2d, Draw2dTarget};
+use crate::frame_stats::FrameStats;
+use crate::gpu_timeline::{FrameTimeline, GpuTimeline};
+use crate::input_recording::{is_recordable, InputRecorder, InputReplay};
+use crate::pipeline::GraphicsPipelineBuilder;
+#[cfg(feature = "renderdoc")]
+use crate::renderdoc::RenderDoc;
 use crate::vk_utils::{
-    create_device, create_entry, create_instance, create_surface, find_queue_family_indices,
-    select_physical_device,
+    begin_label, choose_surface_format, clamp_sample_count, create_device, create_entry,
+    create_index_buffer, create_instance, create_pipeline_cache, create_preset_sampler,
+    create_surface, create_timestamp_pool, depth_stencil_aspect_mask,
+    depth_stencil_attachment_info, end_label, enumerate_instance_extensions,
+    find_queue_family_indices, format_has_stencil, image_barrier, is_sparse_residency_supported,
+    is_srgb_format, is_view_format_compatible, load_device_fault_fn, load_shader_module,
+    load_shader_module_from_bytes, load_shader_module_from_file, load_texture_2d,
+    log_device_fault_info, query_max_multiview_view_count, query_optional_device_support,
+    query_subgroup_properties, query_surface_capabilities, readback_image,
+    select_best_physical_device, select_physical_device, serialize_pipeline_cache, set_object_name,
+    timestamp_valid_bits, AllocatedBuffer, AttachmentImage, DebugMessageFilter, IndexBuffer,
+    IndexType, PhysicalDeviceInfo, QueueFamilyIndices, SamplerPreset, SubgroupProperties,
+    SurfaceCapabilities, Texture2D, UniformBuffer, DEPTH_STENCIL_ATTACHMENT_LAYOUT,
 };
+#[cfg(feature = "validation_layers")]
+use crate::vk_utils::{create_debug_messenger, DebugMessengerState};
+
+pub mod assets;
+pub mod color;
+pub mod deferred_destroy;
+pub mod descriptor;
+mod draw_2d;
+pub mod frame_stats;
+pub mod gpu_timeline;
+mod input_recording;
+pub mod math;
+pub mod pipeline;
+#[cfg(feature = "renderdoc")]
+mod renderdoc;
+pub mod vk_utils;
 
-mod vk_utils;
+pub use runtime_macros::include_spirv;
 
 struct SwapchainHolder {
     swapchain: SwapchainKHR,
+    format: Format,
+    // differs from `format` when the app asked for a sRGB/UNORM reinterpretation
+    view_format: Format,
+    color_space: ColorSpaceKHR,
+    extent: Extent2D,
+    pre_transform: SurfaceTransformFlagsKHR,
+    composite_alpha: CompositeAlphaFlagsKHR,
+    // the presentation engine scales the images when the window size changes
+    scales_on_resize: bool,
+    // TRANSFER_SRC and TRANSFER_DST are only included when the surface supports them, see
+    // `capture_frame` and `cmd_blit_render_target_to_swapchain`
+    image_usage: ImageUsageFlags,
     images: Vec<Image>,
     image_views: Vec<ImageView>,
+    // kept in `DEPTH_STENCIL_ATTACHMENT_LAYOUT` whatever the format, see `App::depth_format`
+    depth: Option<AttachmentImage>,
+    // resolved into the rendered image, see `App::msaa_samples`
+    msaa_color: Option<AttachmentImage>,
 }
 
 impl SwapchainHolder {
-    fn destroy(self, vk: &Vk) {
+    fn destroy(self, vk: &mut Vk) {
+        for attachment in self.depth.into_iter().chain(self.msaa_color) {
+            if let Err(err) = attachment.destroy(vk) {
+                log::error!("failed to destroy swapchain attachment: {err}");
+            }
+        }
         unsafe {
             for image_view in self.image_views {
                 vk.device().destroy_image_view(image_view, None)
@@ -39,159 +120,2520 @@ impl SwapchainHolder {
     }
 }
 
+// depth and multisampled color attachments created along with a swapchain
+struct SwapchainAttachmentsDesc {
+    depth_format: Option<Format>,
+    // a multisampled color attachment is only created for more than one sample
+    samples: SampleCountFlags,
+    // covers both the swapchain and the render target
+    extent: Extent2D,
+    layers: u32,
+    // see `App::use_transient_attachments`
+    transient: bool,
+}
+
+// offscreen color target the app renders into when using a render scale or multiview,
+// blitted to the swapchain at the end of the frame, has a layer per view with multiview
+// the image shares the swapchain format so the blit doesn't convert, the view is created
+// with the swapchain view format
+struct RenderTarget {
+    image: Image,
+    allocation: Allocation,
+    image_view: ImageView,
+    format: Format,
+    extent: Extent2D,
+    layers: u32,
+}
+
+impl RenderTarget {
+    fn new(
+        vk: &mut Vk,
+        format: Format,
+        view_format: Format,
+        extent: Extent2D,
+        layers: u32,
+    ) -> anyhow::Result<Self> {
+        let flags = if view_format != format {
+            ImageCreateFlags::MUTABLE_FORMAT
+        } else {
+            ImageCreateFlags::empty()
+        };
+        let create_info = ImageCreateInfo::builder()
+            .flags(flags)
+            .image_type(ImageType::TYPE_2D)
+            .format(format)
+            .extent(Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(layers)
+            .samples(SampleCountFlags::TYPE_1)
+            .tiling(ImageTiling::OPTIMAL)
+            .usage(
+                ImageUsageFlags::COLOR_ATTACHMENT
+                    | ImageUsageFlags::TRANSFER_SRC
+                    | ImageUsageFlags::SAMPLED,
+            )
+            .sharing_mode(SharingMode::EXCLUSIVE)
+            .initial_layout(ImageLayout::UNDEFINED)
+            .build();
+        let (image, allocation) = vk.allocate_image(&create_info, MemoryLocation::GpuOnly)?;
+
+        let create_info = ImageViewCreateInfo::builder()
+            .format(view_format)
+            .view_type(if layers > 1 {
+                ImageViewType::TYPE_2D_ARRAY
+            } else {
+                ImageViewType::TYPE_2D
+            })
+            .image(image)
+            .components(ComponentMapping::builder().build())
+            .subresource_range(ImageSubresourceRange {
+                layer_count: layers,
+                ..color_subresource_range()
+            })
+            .build();
+        let image_view = match unsafe { vk.device().create_image_view(&create_info, None) } {
+            Ok(image_view) => image_view,
+            Err(err) => {
+                vk.destroy_image(image, allocation)?;
+                return Err(err).context("failed to create render target image view");
+            }
+        };
+
+        Ok(Self {
+            image,
+            allocation,
+            image_view,
+            format,
+            extent,
+            layers,
+        })
+    }
+
+    fn destroy(self, vk: &mut Vk) -> anyhow::Result<()> {
+        unsafe { vk.device().destroy_image_view(self.image_view, None) };
+        vk.destroy_image(self.image, self.allocation)
+    }
+}
+
 // Vk context object
 // uses ManuallyDrop to control drop order
 pub struct Vk {
     entry: ManuallyDrop<Entry>,
     khr_surface: ManuallyDrop<ash::extensions::khr::Surface>,
+    // only present when VK_KHR_get_surface_capabilities2 is supported
+    khr_get_surface_capabilities2: Option<ash::extensions::khr::GetSurfaceCapabilities2>,
+    // VK_EXT_surface_maintenance1, requires VK_KHR_get_surface_capabilities2
+    surface_maintenance1_supported: bool,
+    swapchain_maintenance1_supported: bool,
+    swapchain_mutable_format_supported: bool,
+    image_format_list_supported: bool,
     khr_swapchain: ManuallyDrop<ash::extensions::khr::Swapchain>,
     instance: ManuallyDrop<Instance>,
+    #[cfg(feature = "validation_layers")]
+    debug_utils: ManuallyDrop<ash::extensions::ext::DebugUtils>,
+    #[cfg(feature = "validation_layers")]
+    debug_messenger: DebugUtilsMessengerEXT,
+    // boxed so the pointer handed to the messenger stays valid, dropped after the messenger
+    #[cfg(feature = "validation_layers")]
+    debug_messenger_state: Box<DebugMessengerState>,
     physical_device: ManuallyDrop<PhysicalDevice>,
-    queue_family_idx: u32,
+    queue_family_indices: QueueFamilyIndices,
     device: ManuallyDrop<Device>,
+    // graphics + compute
     queue: ManuallyDrop<Queue>,
+    // may be the same queue as `queue`
+    present_queue: ManuallyDrop<Queue>,
+    // only present when the device has a dedicated transfer queue family
+    transfer_queue: Option<Queue>,
+    transfer_command_pool: Option<CommandPool>,
+    // used by `immediate_submit`, the fence is kept unsignaled between submits
+    immediate_command_pool: CommandPool,
+    immediate_command_buffer: CommandBuffer,
+    immediate_fence: Fence,
+    // used by every pipeline the runtime creates, see `merge_pipeline_cache_data`
+    pipeline_cache: PipelineCache,
+    // created on first use by `sampler`, destroyed with the device
+    samplers: HashMap<SamplerPreset, Sampler>,
+    // ranges of every layout made by `vk_utils::create_pipeline_layout`, checked when
+    // pushing constants through `AppContext::push_constants`
+    #[cfg(debug_assertions)]
+    push_constant_ranges: Mutex<HashMap<PipelineLayout, Vec<PushConstantRange>>>,
+    // locked per allocation so buffers and images can be created through `&Vk`, shared with
+    // `AllocatedBuffer`s so they can free themselves on drop
+    allocator: ManuallyDrop<Arc<Mutex<Allocator>>>,
+    // only present when VK_EXT_device_fault is supported
+    device_fault: Option<ash::vk::ExtDeviceFaultFn>,
+    // only present when VK_KHR_present_id and VK_KHR_present_wait are supported
+    khr_present_wait: Option<ash::extensions::khr::PresentWait>,
+    // only present when VK_KHR_synchronization2 is supported
+    khr_synchronization2: Option<ash::extensions::khr::Synchronization2>,
+    // only present below vulkan 1.3, where dynamic rendering isn't core
+    khr_dynamic_rendering: Option<ash::extensions::khr::DynamicRendering>,
+    sparse_residency_supported: bool,
+    subgroup_properties: SubgroupProperties,
+    // 0 when multiview isn't supported
+    max_multiview_view_count: u32,
+    timeline_semaphore_supported: bool,
+    api_version: u32,
+    enabled_features: PhysicalDeviceFeatures,
+    // replaces the swapchain when created through `VkHeadlessBuilder`
+    offscreen: Option<RenderTarget>,
 }
 
 impl Vk {
-    fn new(display_handle: &dyn HasRawDisplayHandle) -> anyhow::Result<Self> {
+    // also creates the surface for `window` since present support has to be checked
+    // against it when picking queue families, without a window (headless) no surface
+    // extensions or swapchain support are enabled and no surface is returned
+    #[allow(clippy::too_many_arguments)]
+    fn create(
+        window: Option<(&dyn HasRawDisplayHandle, &dyn HasRawWindowHandle)>,
+        additional_instance_extensions: &[CString],
+        additional_instance_layers: &[CString],
+        additional_device_extensions: &[CString],
+        application_name: &str,
+        application_version: u32,
+        api_version: u32,
+        required_features: &PhysicalDeviceFeatures,
+        robust_buffer_access: bool,
+        debug_message_filter: Option<DebugMessageFilter>,
+        select_device: &dyn Fn(&[PhysicalDeviceInfo]) -> anyhow::Result<&PhysicalDeviceInfo>,
+    ) -> anyhow::Result<(Self, Option<SurfaceKHR>)> {
         let entry = create_entry()?;
-        let instance = create_instance(&entry, display_handle)?;
-        let required_device_extensions = get_required_device_extensions();
-        let physical_device = select_physical_device(&instance, &required_device_extensions)?;
-        let queue_family_idx = find_queue_family_indices(&instance, physical_device);
+        let available_instance_extensions = enumerate_instance_extensions(&entry)?;
+        let surface_capabilities2_supported = window.is_some()
+            && available_instance_extensions
+                .contains(ash::extensions::khr::GetSurfaceCapabilities2::name());
+        let surface_maintenance1_supported = surface_capabilities2_supported
+            && available_instance_extensions.contains(ash::vk::ExtSurfaceMaintenance1Fn::name());
+        let mut instance_extensions = additional_instance_extensions.to_vec();
+        if surface_capabilities2_supported {
+            instance_extensions.push(ash::extensions::khr::GetSurfaceCapabilities2::name().into());
+        }
+        if surface_maintenance1_supported {
+            instance_extensions.push(ash::vk::ExtSurfaceMaintenance1Fn::name().into());
+        }
+        let instance = create_instance(
+            &entry,
+            window.map(|(display_handle, _)| display_handle),
+            &instance_extensions,
+            additional_instance_layers,
+            application_name,
+            application_version,
+            api_version,
+        )?;
+        #[cfg(feature = "validation_layers")]
+        let debug_utils = ash::extensions::ext::DebugUtils::new(&entry, &instance);
+        #[cfg(feature = "validation_layers")]
+        let debug_messenger_state = Box::new(DebugMessengerState::new(debug_message_filter));
+        #[cfg(feature = "validation_layers")]
+        let debug_messenger = create_debug_messenger(&debug_utils, &debug_messenger_state)?;
+        #[cfg(not(feature = "validation_layers"))]
+        let _ = debug_message_filter;
+        let mut required_device_extensions =
+            get_required_device_extensions(api_version, window.is_some());
+        for extension in additional_device_extensions {
+            if !required_device_extensions.contains(extension) {
+                required_device_extensions.push(extension.clone());
+            }
+        }
+        let physical_device = select_physical_device(
+            &instance,
+            &required_device_extensions,
+            api_version,
+            required_features,
+            select_device,
+        )?;
+        let khr_surface = ash::extensions::khr::Surface::new(&entry, &instance);
+        let khr_get_surface_capabilities2 = surface_capabilities2_supported
+            .then(|| ash::extensions::khr::GetSurfaceCapabilities2::new(&entry, &instance));
+        let surface = window
+            .map(|(display_handle, window_handle)| {
+                create_surface(&entry, &instance, display_handle, window_handle)
+            })
+            .transpose()?;
+        let queue_family_indices =
+            find_queue_family_indices(&instance, &khr_surface, physical_device, surface)?;
+        if queue_family_indices.is_separate_present() {
+            log::info!("using a separate present queue family {queue_family_indices:?}");
+        }
+        let mut optional_support =
+            query_optional_device_support(&instance, physical_device, api_version)?;
+        let robust_buffer_access = robust_buffer_access
+            && unsafe { instance.get_physical_device_features(physical_device) }
+                .robust_buffer_access
+                == ash::vk::TRUE;
+        if robust_buffer_access {
+            log::info!("robust buffer access enabled, robustness2 support {optional_support:?}");
+        } else {
+            // robustness2 has a performance cost too, only enable it on request
+            optional_support.robust_buffer_access2 = false;
+            optional_support.robust_image_access2 = false;
+            optional_support.null_descriptor = false;
+        }
+        if !surface_maintenance1_supported {
+            optional_support.swapchain_maintenance1 = false;
+        }
+        if window.is_none() {
+            // VK_KHR_present_id and VK_KHR_swapchain_mutable_format depend on VK_KHR_swapchain
+            optional_support.present_wait = false;
+            optional_support.swapchain_mutable_format = false;
+        }
+        let sparse_residency_supported = is_sparse_residency_supported(
+            &instance,
+            physical_device,
+            queue_family_indices.graphics,
+        );
+        let subgroup_properties = query_subgroup_properties(
+            &instance,
+            physical_device,
+            optional_support.subgroup_size_control,
+        );
+        let max_multiview_view_count =
+            query_max_multiview_view_count(&instance, physical_device, optional_support.multiview);
+        let mut enabled_features = *required_features;
+        if robust_buffer_access {
+            enabled_features.robust_buffer_access = ash::vk::TRUE;
+        }
+        if sparse_residency_supported {
+            enabled_features.sparse_binding = ash::vk::TRUE;
+            enabled_features.sparse_residency_image2_d = ash::vk::TRUE;
+        }
         let device = create_device(
             &instance,
             physical_device,
-            queue_family_idx,
+            &queue_family_indices,
             &required_device_extensions,
+            &enabled_features,
+            optional_support,
+            api_version,
         )?;
-        let khr_surface = ash::extensions::khr::Surface::new(&entry, &instance);
+        let device_fault = optional_support
+            .device_fault
+            .then(|| load_device_fault_fn(&instance, &device));
+        let khr_present_wait = optional_support
+            .present_wait
+            .then(|| ash::extensions::khr::PresentWait::new(&instance, &device));
+        let khr_dynamic_rendering = (api_version < ash::vk::API_VERSION_1_3)
+            .then(|| ash::extensions::khr::DynamicRendering::new(&instance, &device));
+        let khr_synchronization2 = optional_support
+            .synchronization2
+            .then(|| ash::extensions::khr::Synchronization2::new(&instance, &device));
         let khr_swapchain = ash::extensions::khr::Swapchain::new(&instance, &device);
-        let queue = unsafe { device.get_device_queue(queue_family_idx, 0) };
-        Ok(Self {
+        let queue = unsafe { device.get_device_queue(queue_family_indices.graphics, 0) };
+        let present_queue = unsafe { device.get_device_queue(queue_family_indices.present, 0) };
+        let transfer_queue = queue_family_indices
+            .transfer
+            .map(|idx| unsafe { device.get_device_queue(idx, 0) });
+        let transfer_command_pool = match queue_family_indices.transfer {
+            Some(idx) => {
+                let create_info = CommandPoolCreateInfo::builder()
+                    .queue_family_index(idx)
+                    .flags(CommandPoolCreateFlags::TRANSIENT)
+                    .build();
+                Some(unsafe {
+                    device
+                        .create_command_pool(&create_info, None)
+                        .context("failed to create transfer command pool")?
+                })
+            }
+            None => None,
+        };
+        let immediate_command_pool = unsafe {
+            device
+                .create_command_pool(
+                    &CommandPoolCreateInfo::builder()
+                        .queue_family_index(queue_family_indices.graphics)
+                        .flags(
+                            CommandPoolCreateFlags::TRANSIENT
+                                | CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+                        )
+                        .build(),
+                    None,
+                )
+                .context("failed to create immediate command pool")?
+        };
+        let immediate_command_buffer = unsafe {
+            device
+                .allocate_command_buffers(
+                    &CommandBufferAllocateInfo::builder()
+                        .command_pool(immediate_command_pool)
+                        .level(CommandBufferLevel::PRIMARY)
+                        .command_buffer_count(1)
+                        .build(),
+                )
+                .context("failed to allocate immediate command buffer")?[0]
+        };
+        let immediate_fence = unsafe {
+            device
+                .create_fence(&FenceCreateInfo::default(), None)
+                .context("failed to create immediate fence")?
+        };
+        let pipeline_cache = unsafe {
+            device
+                .create_pipeline_cache(&PipelineCacheCreateInfo::default(), None)
+                .context("failed to create pipeline cache")?
+        };
+        let allocator = Allocator::new(&AllocatorCreateDesc {
+            instance: instance.clone(),
+            device: device.clone(),
+            physical_device,
+            debug_settings: Default::default(),
+            buffer_device_address: false,
+        })
+        .context("failed to create allocator")?;
+        let vk = Self {
             entry: ManuallyDrop::new(entry),
             khr_surface: ManuallyDrop::new(khr_surface),
+            khr_get_surface_capabilities2,
+            surface_maintenance1_supported,
+            swapchain_maintenance1_supported: optional_support.swapchain_maintenance1,
+            swapchain_mutable_format_supported: optional_support.swapchain_mutable_format,
+            image_format_list_supported: optional_support.image_format_list,
             khr_swapchain: ManuallyDrop::new(khr_swapchain),
             instance: ManuallyDrop::new(instance),
+            #[cfg(feature = "validation_layers")]
+            debug_utils: ManuallyDrop::new(debug_utils),
+            #[cfg(feature = "validation_layers")]
+            debug_messenger,
+            #[cfg(feature = "validation_layers")]
+            debug_messenger_state,
             physical_device: ManuallyDrop::new(physical_device),
-            queue_family_idx,
+            queue_family_indices,
             device: ManuallyDrop::new(device),
             queue: ManuallyDrop::new(queue),
-        })
+            present_queue: ManuallyDrop::new(present_queue),
+            transfer_queue,
+            transfer_command_pool,
+            immediate_command_pool,
+            immediate_command_buffer,
+            immediate_fence,
+            pipeline_cache,
+            samplers: HashMap::new(),
+            #[cfg(debug_assertions)]
+            push_constant_ranges: Mutex::default(),
+            allocator: ManuallyDrop::new(Arc::new(Mutex::new(allocator))),
+            device_fault,
+            khr_present_wait,
+            khr_synchronization2,
+            khr_dynamic_rendering,
+            sparse_residency_supported,
+            subgroup_properties,
+            max_multiview_view_count,
+            timeline_semaphore_supported: optional_support.timeline_semaphore,
+            api_version,
+            enabled_features,
+            offscreen: None,
+        };
+
+        Ok((vk, surface))
+    }
+
+    pub fn entry(&self) -> &Entry {
+        &self.entry
+    }
+
+    pub fn khr_surface(&self) -> &ash::extensions::khr::Surface {
+        &self.khr_surface
+    }
+
+    pub fn khr_get_surface_capabilities2(
+        &self,
+    ) -> Option<&ash::extensions::khr::GetSurfaceCapabilities2> {
+        self.khr_get_surface_capabilities2.as_ref()
+    }
+
+    pub fn supports_surface_maintenance1(&self) -> bool {
+        self.surface_maintenance1_supported
+    }
+
+    pub fn supports_swapchain_maintenance1(&self) -> bool {
+        self.swapchain_maintenance1_supported
+    }
+
+    // swapchain image views may use a different format than the swapchain images
+    pub fn supports_swapchain_mutable_format(&self) -> bool {
+        self.swapchain_mutable_format_supported
+    }
+
+    // image creation may list the formats a MUTABLE_FORMAT image will be viewed as
+    pub fn supports_image_format_list(&self) -> bool {
+        self.image_format_list_supported
+    }
+
+    // present scaling is only reported when VK_EXT_surface_maintenance1 is supported
+    pub fn query_surface_capabilities(
+        &self,
+        surface: SurfaceKHR,
+        present_mode: PresentModeKHR,
+    ) -> anyhow::Result<SurfaceCapabilities> {
+        query_surface_capabilities(
+            &self.khr_surface,
+            self.khr_get_surface_capabilities2.as_ref(),
+            self.surface_maintenance1_supported,
+            *self.physical_device,
+            surface,
+            present_mode,
+        )
+    }
+
+    pub fn khr_swapchain(&self) -> &ash::extensions::khr::Swapchain {
+        &self.khr_swapchain
+    }
+
+    pub fn instance(&self) -> &Instance {
+        &self.instance
+    }
+
+    pub fn physical_device(&self) -> &PhysicalDevice {
+        &self.physical_device
+    }
+
+    pub fn queue_family_idx(&self) -> u32 {
+        self.queue_family_indices.graphics
+    }
+
+    pub fn present_queue_family_idx(&self) -> u32 {
+        self.queue_family_indices.present
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &Queue {
+        &self.queue
+    }
+
+    pub fn present_queue(&self) -> &Queue {
+        &self.present_queue
+    }
+
+    // uploads can be submitted here to run alongside graphics work, resources then need a
+    // queue family ownership transfer (or CONCURRENT sharing) before the graphics queue uses them
+    pub fn transfer_queue_family(&self) -> Option<u32> {
+        self.queue_family_indices.transfer
+    }
+
+    pub fn transfer_queue(&self) -> Option<Queue> {
+        self.transfer_queue
+    }
+
+    pub fn transfer_command_pool(&self) -> Option<CommandPool> {
+        self.transfer_command_pool
+    }
+
+    // records the commands with `record` into a one time command buffer, submits it to the
+    // graphics queue and blocks until it finished, must not be called from within `record`
+    pub fn immediate_submit(&self, record: impl FnOnce(CommandBuffer)) -> anyhow::Result<()> {
+        let cmd = self.immediate_command_buffer;
+        unsafe {
+            self.device
+                .reset_command_buffer(cmd, CommandBufferResetFlags::empty())
+                .context("failed to reset immediate command buffer")?;
+            self.device
+                .begin_command_buffer(
+                    cmd,
+                    &CommandBufferBeginInfo::builder()
+                        .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+                        .build(),
+                )
+                .context("failed to begin immediate command buffer")?;
+            record(cmd);
+            self.device
+                .end_command_buffer(cmd)
+                .context("failed to end immediate command buffer")?;
+
+            let command_buffers = [cmd];
+            let submit_info = SubmitInfo::builder()
+                .command_buffers(&command_buffers)
+                .build();
+            self.device
+                .queue_submit(*self.queue, &[submit_info], self.immediate_fence)
+                .context("failed to submit immediate command buffer")?;
+            self.device
+                .wait_for_fences(&[self.immediate_fence], true, u64::MAX)
+                .context("failed to wait for immediate submit")?;
+            self.device
+                .reset_fences(&[self.immediate_fence])
+                .context("failed to reset immediate fence")
+        }
+    }
+
+    // only present when created through `VkHeadlessBuilder`, kept in
+    // COLOR_ATTACHMENT_OPTIMAL so it can be rendered to with `cmd_begin_rendering`
+    pub fn offscreen_image(&self) -> Option<Image> {
+        self.offscreen.as_ref().map(|e| e.image)
+    }
+
+    pub fn offscreen_image_view(&self) -> Option<ImageView> {
+        self.offscreen.as_ref().map(|e| e.image_view)
+    }
+
+    pub fn offscreen_extent(&self) -> Option<Extent2D> {
+        self.offscreen.as_ref().map(|e| e.extent)
+    }
+
+    // copies the offscreen image back as tightly packed RGBA8 rows, see
+    // `vk_utils::readback_image`, waits for the copy so it's meant for tests and benchmarks
+    pub fn readback_to_cpu(&mut self, cmd_pool: CommandPool) -> anyhow::Result<Vec<u8>> {
+        let Some(offscreen) = &self.offscreen else {
+            bail!("no offscreen image, the Vk wasn't created through VkHeadlessBuilder");
+        };
+        let (image, format, extent) = (offscreen.image, offscreen.format, offscreen.extent);
+        readback_image(
+            self,
+            cmd_pool,
+            image,
+            format,
+            extent,
+            ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        )
+    }
+
+    pub fn pipeline_cache(&self) -> PipelineCache {
+        self.pipeline_cache
+    }
+
+    // adds cache data saved by a previous run (`vk_utils::serialize_pipeline_cache`),
+    // data from another device or driver version is ignored by the driver
+    pub fn merge_pipeline_cache_data(&self, data: &[u8]) -> anyhow::Result<()> {
+        let source = create_pipeline_cache(self, Some(data))?;
+        let result = unsafe {
+            self.device
+                .merge_pipeline_caches(self.pipeline_cache, &[source])
+                .context("failed to merge pipeline caches")
+        };
+        unsafe { self.device.destroy_pipeline_cache(source, None) };
+        result
+    }
+
+    pub(crate) fn register_push_constant_ranges(
+        &self,
+        layout: PipelineLayout,
+        ranges: &[PushConstantRange],
+    ) {
+        #[cfg(debug_assertions)]
+        if let Ok(mut registered) = self.push_constant_ranges.lock() {
+            registered.insert(layout, ranges.to_vec());
+        }
+        #[cfg(not(debug_assertions))]
+        let _ = (layout, ranges);
+    }
+
+    // layouts that weren't created through `vk_utils::create_pipeline_layout` aren't checked
+    #[cfg(debug_assertions)]
+    fn validate_push_constants(
+        &self,
+        layout: PipelineLayout,
+        stages: ShaderStageFlags,
+        offset: u32,
+        size: u32,
+    ) -> anyhow::Result<()> {
+        let Ok(registered) = self.push_constant_ranges.lock() else {
+            return Ok(());
+        };
+        let Some(ranges) = registered.get(&layout) else {
+            return Ok(());
+        };
+        let end = offset as u64 + size as u64;
+        let fits = ranges.iter().any(|range| {
+            range.stage_flags.contains(stages)
+                && range.offset <= offset
+                && end <= range.offset as u64 + range.size as u64
+        });
+        if !fits {
+            bail!(
+                "{size} push constant bytes at offset {offset} for {stages:?} don't fit any range of the layout {ranges:?}"
+            );
+        }
+        Ok(())
+    }
+
+    // the same sampler is returned for every request of `preset`, it must not be destroyed
+    pub fn sampler(&mut self, preset: SamplerPreset) -> anyhow::Result<Sampler> {
+        if let Some(sampler) = self.samplers.get(&preset) {
+            return Ok(*sampler);
+        }
+        let sampler = create_preset_sampler(self, preset)?;
+        self.samplers.insert(preset, sampler);
+        Ok(sampler)
+    }
+
+    // validates the SPIR-V magic number before creating the module
+    pub fn create_shader_module(&self, spirv: &[u32]) -> anyhow::Result<ShaderModule> {
+        load_shader_module(self, spirv)
+    }
+
+    pub fn load_shader_module_from_bytes(&self, bytes: &[u8]) -> anyhow::Result<ShaderModule> {
+        load_shader_module_from_bytes(self, bytes)
+    }
+
+    // modules can be destroyed as soon as the pipelines using them are created
+    pub fn destroy_shader_module(&self, module: ShaderModule) {
+        unsafe { self.device.destroy_shader_module(module, None) };
+    }
+
+    // also forgets the push constant ranges registered for it, a new layout may reuse the
+    // handle
+    pub fn destroy_pipeline_layout(&self, layout: PipelineLayout) {
+        #[cfg(debug_assertions)]
+        if let Ok(mut registered) = self.push_constant_ranges.lock() {
+            registered.remove(&layout);
+        }
+        unsafe { self.device.destroy_pipeline_layout(layout, None) };
+    }
+
+    // `App::required_device_features` plus whatever the runtime enabled itself
+    pub fn enabled_features(&self) -> &PhysicalDeviceFeatures {
+        &self.enabled_features
+    }
+
+    // the version requested through `App::api_version`, supported by the selected device
+    pub fn api_version(&self) -> u32 {
+        self.api_version
+    }
+
+    pub fn khr_synchronization2(&self) -> Option<&ash::extensions::khr::Synchronization2> {
+        self.khr_synchronization2.as_ref()
+    }
+
+    pub fn supports_timeline_semaphores(&self) -> bool {
+        self.timeline_semaphore_supported
+    }
+
+    pub fn subgroup_properties(&self) -> SubgroupProperties {
+        self.subgroup_properties
+    }
+
+    // largest width and height of a 2d image, textures above it fail to load
+    pub fn max_image_dimension_2d(&self) -> u32 {
+        unsafe {
+            self.instance
+                .get_physical_device_properties(*self.physical_device)
+        }
+        .limits
+        .max_image_dimension2_d
+    }
+
+    // views a single multiview draw can render to, 0 when multiview isn't supported
+    pub fn max_multiview_view_count(&self) -> u32 {
+        self.max_multiview_view_count
+    }
+
+    // labels show up in RenderDoc and validation messages, no-op without the
+    // `validation_layers` feature
+    pub fn cmd_begin_debug_label(&self, cmd: CommandBuffer, name: &str) {
+        begin_label(self, cmd, name, [0.0; 4]);
+    }
+
+    pub fn cmd_end_debug_label(&self, cmd: CommandBuffer) {
+        end_label(self, cmd);
+    }
+
+    // dispatches to the core or VK_KHR_dynamic_rendering entry point depending on the api version
+    pub fn cmd_begin_rendering(&self, cmd: CommandBuffer, rendering_info: &RenderingInfoKHR) {
+        unsafe {
+            match &self.khr_dynamic_rendering {
+                Some(khr_dynamic_rendering) => {
+                    khr_dynamic_rendering.cmd_begin_rendering(cmd, rendering_info)
+                }
+                None => self.device.cmd_begin_rendering(cmd, rendering_info),
+            }
+        }
+    }
+
+    pub fn cmd_end_rendering(&self, cmd: CommandBuffer) {
+        unsafe {
+            match &self.khr_dynamic_rendering {
+                Some(khr_dynamic_rendering) => khr_dynamic_rendering.cmd_end_rendering(cmd),
+                None => self.device.cmd_end_rendering(cmd),
+            }
+        }
+    }
+
+    // whole image color transition, see `vk_utils::image_barrier` for other aspects
+    pub fn transition_image_layout(
+        &self,
+        cmd: CommandBuffer,
+        image: Image,
+        old_layout: ImageLayout,
+        new_layout: ImageLayout,
+    ) {
+        image_barrier(
+            self,
+            cmd,
+            image,
+            old_layout,
+            new_layout,
+            ImageAspectFlags::COLOR,
+        );
+    }
+
+    // validation errors reported so far, always 0 without the `validation_layers` feature
+    pub fn validation_error_count(&self) -> u64 {
+        #[cfg(feature = "validation_layers")]
+        return self.debug_messenger_state.error_count();
+        #[cfg(not(feature = "validation_layers"))]
+        0
+    }
+
+    pub fn validation_warning_count(&self) -> u64 {
+        #[cfg(feature = "validation_layers")]
+        return self.debug_messenger_state.warning_count();
+        #[cfg(not(feature = "validation_layers"))]
+        0
+    }
+
+    // logs whatever the driver knows about the last device lost, no-op if unsupported
+    pub fn report_device_fault(&self) {
+        match &self.device_fault {
+            Some(device_fault) => log_device_fault_info(&self.device, device_fault),
+            None => log::error!("device lost, VK_EXT_device_fault not supported"),
+        }
+    }
+
+    // true if sparseBinding and sparseResidencyImage2D are enabled
+    pub fn supports_sparse_residency(&self) -> bool {
+        self.sparse_residency_supported
+    }
+
+    // creates an image with no memory bound, use `sparse_bind` to back regions of it
+    pub fn create_sparse_image(&self, create_info: &ImageCreateInfo) -> anyhow::Result<Image> {
+        if !self.sparse_residency_supported {
+            bail!("sparse residency is not supported by the device");
+        }
+
+        let mut create_info = *create_info;
+        create_info.flags |= ImageCreateFlags::SPARSE_BINDING | ImageCreateFlags::SPARSE_RESIDENCY;
+        unsafe {
+            self.device
+                .create_image(&create_info, None)
+                .context("failed to create sparse image")
+        }
+    }
+
+    pub fn sparse_bind(&self, bind_infos: &[BindSparseInfo], fence: Fence) -> anyhow::Result<()> {
+        if !self.sparse_residency_supported {
+            bail!("sparse residency is not supported by the device");
+        }
+
+        unsafe {
+            self.device
+                .queue_bind_sparse(*self.queue, bind_infos, fence)
+                .context("failed to bind sparse memory")
+        }
+    }
+
+    // a panic while allocating leaves gpu-allocator's bookkeeping intact, so a poisoned
+    // lock is still used
+    pub fn allocator(&self) -> MutexGuard<'_, Allocator> {
+        self.allocator
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn shared_allocator(&self) -> Arc<Mutex<Allocator>> {
+        Arc::clone(&self.allocator)
+    }
+
+    // GpuOnly buffers also get TRANSFER_DST usage so `upload_to_buffer` can fill them, the
+    // buffer is freed when dropped
+    pub fn create_buffer(
+        &self,
+        size: u64,
+        usage: BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> anyhow::Result<AllocatedBuffer> {
+        AllocatedBuffer::new(self, size, usage, location)
+    }
+
+    // the memory is bound already, both are released with `destroy_buffer`, see
+    // `create_buffer` for a wrapper that frees itself
+    pub fn allocate_buffer(
+        &self,
+        size: u64,
+        usage: BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> anyhow::Result<(Buffer, Allocation)> {
+        let create_info = BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(SharingMode::EXCLUSIVE)
+            .build();
+
+        let buffer = unsafe {
+            self.device
+                .create_buffer(&create_info, None)
+                .context("failed to create buffer")?
+        };
+        let requirements = unsafe { self.device.get_buffer_memory_requirements(buffer) };
+
+        let allocation = match self.allocator().allocate(&AllocationCreateDesc {
+            name: "buffer",
+            requirements,
+            location,
+            linear: true,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        }) {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                unsafe { self.device.destroy_buffer(buffer, None) };
+                return Err(err).context("failed to allocate buffer memory");
+            }
+        };
+
+        if let Err(err) = unsafe {
+            self.device
+                .bind_buffer_memory(buffer, allocation.memory(), allocation.offset())
+        } {
+            self.destroy_buffer(buffer, allocation)?;
+            return Err(err).context("failed to bind buffer memory");
+        }
+
+        Ok((buffer, allocation))
+    }
+
+    // writes `data` to the start of `buffer`, directly when its memory is host visible,
+    // otherwise through a staging buffer and `immediate_submit`, which blocks until done
+    pub fn upload_to_buffer(&self, buffer: &AllocatedBuffer, data: &[u8]) -> anyhow::Result<()> {
+        if data.len() as u64 > buffer.size() {
+            bail!(
+                "{} bytes don't fit into a buffer of {} bytes",
+                data.len(),
+                buffer.size()
+            );
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(mapped_ptr) = buffer.allocation().mapped_ptr() {
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    data.as_ptr(),
+                    mapped_ptr.as_ptr().cast::<u8>(),
+                    data.len(),
+                )
+            };
+            return Ok(());
+        }
+
+        let (staging_buffer, staging_allocation) = self.allocate_buffer(
+            data.len() as u64,
+            BufferUsageFlags::TRANSFER_SRC,
+            MemoryLocation::CpuToGpu,
+        )?;
+        let Some(mapped_ptr) = staging_allocation.mapped_ptr() else {
+            self.destroy_buffer(staging_buffer, staging_allocation)?;
+            bail!("staging buffer memory is not host visible");
+        };
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                mapped_ptr.as_ptr().cast::<u8>(),
+                data.len(),
+            )
+        };
+
+        let copy_result = self.immediate_submit(|cmd| unsafe {
+            let region = BufferCopy::builder().size(data.len() as u64).build();
+            self.device
+                .cmd_copy_buffer(cmd, staging_buffer, buffer.buffer(), &[region]);
+        });
+        self.destroy_buffer(staging_buffer, staging_allocation)?;
+        copy_result
+    }
+
+    // `vertices` are copied byte for byte, `T` should be `#[repr(C)]` so its layout matches
+    // the pipeline's vertex input attributes, the buffer starts at offset 0 which satisfies
+    // the alignment of any vertex format
+    pub fn create_vertex_buffer<T: Copy>(&self, vertices: &[T]) -> anyhow::Result<AllocatedBuffer> {
+        self.create_device_local_buffer(vertices, BufferUsageFlags::VERTEX_BUFFER)
+    }
+
+    // u16 or u32 indices, the index type is derived from `T`, see `IndexBuffer::bind`
+    pub fn create_index_buffer<T: Copy + IndexType>(
+        &self,
+        indices: &[T],
+    ) -> anyhow::Result<IndexBuffer> {
+        create_index_buffer(self, indices)
+    }
+
+    fn create_device_local_buffer<T: Copy>(
+        &self,
+        data: &[T],
+        usage: BufferUsageFlags,
+    ) -> anyhow::Result<AllocatedBuffer> {
+        let size = std::mem::size_of_val(data);
+        if size == 0 {
+            bail!("can't create an empty buffer");
+        }
+        let bytes = unsafe { std::slice::from_raw_parts(data.as_ptr().cast::<u8>(), size) };
+
+        let buffer = self.create_buffer(size as u64, usage, MemoryLocation::GpuOnly)?;
+        self.upload_to_buffer(&buffer, bytes)?;
+
+        Ok(buffer)
+    }
+
+    pub fn destroy_buffer(&self, buffer: Buffer, allocation: Allocation) -> anyhow::Result<()> {
+        unsafe { self.device.destroy_buffer(buffer, None) };
+        self.allocator()
+            .free(allocation)
+            .context("failed to free buffer memory")
+    }
+
+    // the memory is bound already, both are released with `destroy_image`
+    pub fn allocate_image(
+        &self,
+        create_info: &ImageCreateInfo,
+        location: MemoryLocation,
+    ) -> anyhow::Result<(Image, Allocation)> {
+        let image = unsafe {
+            self.device
+                .create_image(create_info, None)
+                .context("failed to create image")?
+        };
+        let requirements = unsafe { self.device.get_image_memory_requirements(image) };
+
+        let allocation = match self.allocator().allocate(&AllocationCreateDesc {
+            name: "image",
+            requirements,
+            location,
+            linear: create_info.tiling == ImageTiling::LINEAR,
+            allocation_scheme: AllocationScheme::GpuAllocatorManaged,
+        }) {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                unsafe { self.device.destroy_image(image, None) };
+                return Err(err).context("failed to allocate image memory");
+            }
+        };
+
+        if let Err(err) = unsafe {
+            self.device
+                .bind_image_memory(image, allocation.memory(), allocation.offset())
+        } {
+            self.destroy_image(image, allocation)?;
+            return Err(err).context("failed to bind image memory");
+        }
+
+        Ok((image, allocation))
+    }
+
+    pub fn destroy_image(&self, image: Image, allocation: Allocation) -> anyhow::Result<()> {
+        unsafe { self.device.destroy_image(image, None) };
+        self.allocator()
+            .free(allocation)
+            .context("failed to free image memory")
+    }
+}
+
+impl Drop for Vk {
+    fn drop(&mut self) {
+        unsafe {
+            if let Some(offscreen) = self.offscreen.take() {
+                if let Err(err) = offscreen.destroy(self) {
+                    log::error!("failed to destroy offscreen image: {err:?}");
+                }
+            }
+            // allocator must release its memory blocks before the device goes away
+            if Arc::strong_count(&self.allocator) > 1 {
+                log::error!("buffers outlived the device, their memory is leaked");
+            }
+            ManuallyDrop::drop(&mut self.allocator);
+            if let Some(command_pool) = self.transfer_command_pool {
+                self.device.destroy_command_pool(command_pool, None);
+            }
+            self.device
+                .destroy_command_pool(self.immediate_command_pool, None);
+            self.device.destroy_fence(self.immediate_fence, None);
+            self.device
+                .destroy_pipeline_cache(self.pipeline_cache, None);
+            for (_, sampler) in self.samplers.drain() {
+                self.device.destroy_sampler(sampler, None);
+            }
+            self.device.destroy_device(None);
+            ManuallyDrop::drop(&mut self.physical_device);
+            #[cfg(feature = "validation_layers")]
+            {
+                self.debug_utils
+                    .destroy_debug_utils_messenger(self.debug_messenger, None);
+                ManuallyDrop::drop(&mut self.debug_utils);
+            }
+            self.instance.destroy_instance(None);
+            ManuallyDrop::drop(&mut self.entry);
+        }
+    }
+}
+
+// creates a `Vk` without a window or swapchain for tests, benchmarks and CI, frames are
+// rendered into an offscreen image instead, see `Vk::offscreen_image`
+pub struct VkHeadlessBuilder {
+    extent: Extent2D,
+    format: Format,
+    application_name: String,
+    application_version: u32,
+    api_version: u32,
+    required_features: PhysicalDeviceFeatures,
+    robust_buffer_access: bool,
+    debug_message_filter: Option<DebugMessageFilter>,
+    additional_instance_extensions: Vec<CString>,
+    additional_instance_layers: Vec<CString>,
+    additional_device_extensions: Vec<CString>,
+}
+
+impl VkHeadlessBuilder {
+    // the defaults match the ones of `App`
+    pub fn new(extent: Extent2D, format: Format) -> Self {
+        Self {
+            extent,
+            format,
+            application_name: "headless".to_string(),
+            application_version: ash::vk::make_api_version(0, 0, 1, 0),
+            api_version: ash::vk::API_VERSION_1_2,
+            required_features: PhysicalDeviceFeatures::default(),
+            robust_buffer_access: false,
+            debug_message_filter: None,
+            additional_instance_extensions: vec![],
+            additional_instance_layers: vec![],
+            additional_device_extensions: vec![],
+        }
+    }
+
+    pub fn application_name(mut self, application_name: &str) -> Self {
+        self.application_name = application_name.to_string();
+        self
+    }
+
+    pub fn application_version(mut self, application_version: u32) -> Self {
+        self.application_version = application_version;
+        self
+    }
+
+    pub fn api_version(mut self, api_version: u32) -> Self {
+        self.api_version = api_version;
+        self
+    }
+
+    pub fn required_features(mut self, required_features: PhysicalDeviceFeatures) -> Self {
+        self.required_features = required_features;
+        self
+    }
+
+    pub fn robust_buffer_access(mut self, robust_buffer_access: bool) -> Self {
+        self.robust_buffer_access = robust_buffer_access;
+        self
+    }
+
+    pub fn debug_message_filter(mut self, debug_message_filter: DebugMessageFilter) -> Self {
+        self.debug_message_filter = Some(debug_message_filter);
+        self
+    }
+
+    pub fn additional_instance_extensions(mut self, extensions: &[CString]) -> Self {
+        self.additional_instance_extensions = extensions.to_vec();
+        self
+    }
+
+    pub fn additional_instance_layers(mut self, layers: &[CString]) -> Self {
+        self.additional_instance_layers = layers.to_vec();
+        self
+    }
+
+    pub fn additional_device_extensions(mut self, extensions: &[CString]) -> Self {
+        self.additional_device_extensions = extensions.to_vec();
+        self
+    }
+
+    pub fn build(self) -> anyhow::Result<Vk> {
+        let (mut vk, _) = Vk::create(
+            None,
+            &self.additional_instance_extensions,
+            &self.additional_instance_layers,
+            &self.additional_device_extensions,
+            &self.application_name,
+            self.application_version,
+            self.api_version,
+            &self.required_features,
+            self.robust_buffer_access,
+            self.debug_message_filter,
+            &select_best_physical_device,
+        )?;
+
+        let format_properties = unsafe {
+            vk.instance()
+                .get_physical_device_format_properties(*vk.physical_device(), self.format)
+        };
+        if !format_properties
+            .optimal_tiling_features
+            .contains(FormatFeatureFlags::COLOR_ATTACHMENT)
+        {
+            bail!("{:?} can't be used as a color attachment", self.format);
+        }
+        let offscreen = RenderTarget::new(&mut vk, self.format, self.format, self.extent, 1)?;
+        let image = offscreen.image;
+        vk.offscreen = Some(offscreen);
+        vk.immediate_submit(|cmd| {
+            vk.transition_image_layout(
+                cmd,
+                image,
+                ImageLayout::UNDEFINED,
+                ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            )
+        })?;
+
+        Ok(vk)
+    }
+}
+
+// returned when a wait fails because the device was lost, use `downcast_ref` to detect it
+#[derive(Debug)]
+pub struct DeviceLost;
+
+impl std::fmt::Display for DeviceLost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "device lost")
+    }
+}
+
+impl std::error::Error for DeviceLost {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SwapchainStatus {
+    Ok(u32),
+    // the image can still be presented, the app may want to recreate the swapchain
+    Suboptimal(u32),
+    // no image was acquired, the swapchain is recreated after the current frame
+    OutOfDate,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresentResult {
+    Ok,
+    Suboptimal,
+    // the swapchain is recreated after the current frame
+    OutOfDate,
+}
+
+// swapchain image contents read back before a present, see `App::capture_presented_frames`
+struct CapturedFrame {
+    pixels: Vec<u8>,
+    extent: Extent2D,
+    format: Format,
+}
+
+// a window with its own surface, swapchain and render target, window 0 is the main window
+pub struct WindowContext {
+    window: Window,
+    events: Receiver<(f64, WindowEvent)>,
+    surface: SurfaceKHR,
+    swapchain: Option<SwapchainHolder>,
+    render_target: Option<RenderTarget>,
+    // last id passed to VK_KHR_present_id, reset when the swapchain is recreated
+    present_id: u64,
+    // set while the framebuffer has a zero extent (e.g. minimized), no swapchain exists
+    paused: bool,
+    // the window was resized while the swapchain scales on resize, it's recreated once the
+    // size stops changing
+    resize_pending: bool,
+    // set when acquire or present reported OUT_OF_DATE and for new windows, the swapchain
+    // is recreated before the window's next frame
+    swapchain_out_of_date: bool,
+    // acquire or present reported SUBOPTIMAL, left to the app since the swapchain still works
+    swapchain_suboptimal: bool,
+}
+
+impl WindowContext {
+    fn new(window: Window, events: Receiver<(f64, WindowEvent)>, surface: SurfaceKHR) -> Self {
+        Self {
+            window,
+            events,
+            surface,
+            swapchain: None,
+            render_target: None,
+            present_id: 0,
+            paused: false,
+            resize_pending: false,
+            swapchain_out_of_date: false,
+            swapchain_suboptimal: false,
+        }
+    }
+
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+
+    // true while the window is minimized, `App::frame` is not called for it in this state
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn swapchain_extent(&self) -> Option<Extent2D> {
+        self.swapchain.as_ref().map(|e| e.extent)
+    }
+
+    pub fn swapchain_format(&self) -> Option<Format> {
+        self.swapchain.as_ref().map(|e| e.format)
+    }
+
+    pub fn swapchain_view_format(&self) -> Option<Format> {
+        self.swapchain.as_ref().map(|e| e.view_format)
+    }
+
+    fn swapchain_holder(&self) -> anyhow::Result<&SwapchainHolder> {
+        self.swapchain
+            .as_ref()
+            .context("swapchain not initialized!")
+    }
+
+    fn render_target(&self) -> anyhow::Result<&RenderTarget> {
+        self.render_target
+            .as_ref()
+            .context("render target not initialized!")
+    }
+
+    fn swapchain_scales_on_resize(&self) -> bool {
+        self.swapchain.as_ref().is_some_and(|e| e.scales_on_resize)
+    }
+
+    // the device must be idle
+    fn destroy(mut self, vk: &mut Vk) {
+        if let Some(render_target) = self.render_target.take() {
+            if let Err(err) = render_target.destroy(vk) {
+                log::error!("failed to destroy render target: {err}");
+            }
+        }
+        if let Some(swapchain) = self.swapchain.take() {
+            swapchain.destroy(vk);
+        }
+        unsafe { vk.khr_surface.destroy_surface(self.surface, None) };
+    }
+}
+
+pub struct AppContext {
+    glfw: Glfw,
+    // indexed by window id, closed windows leave a None so ids stay stable
+    windows: Vec<Option<WindowContext>>,
+    // the window `App::frame` is running for, swapchain methods act on it
+    current_window: usize,
+    vk: Vk,
+    render_scale: f32,
+    multiview_count: u32,
+    // clamped to what the device supports, see `App::msaa_samples`
+    msaa_samples: SampleCountFlags,
+    // one fence per frame allowed in flight, created signaled
+    frame_latency_fences: Vec<Fence>,
+    frame_idx: u64,
+    start_instant: Instant,
+    last_frame_instant: Instant,
+    // time between the starts of the last two frame loops, the first one starts after
+    // `App::init`
+    delta_time: Duration,
+    frame_stats: FrameStats,
+    // the app's title when `App::show_frame_stats_in_title` is set
+    frame_stats_title: Option<String>,
+    last_title_update: Instant,
+    draw_2d: Draw2d,
+    use_transient_attachments: bool,
+    stencil_clear_value: u32,
+    injected_events: Vec<WindowEvent>,
+    asset_resolver: AssetResolver,
+    input_recorder: Option<InputRecorder>,
+    input_replay: Option<InputReplay>,
+    // None when the graphics queue doesn't support timestamps
+    gpu_timeline: Option<GpuTimeline>,
+    // `App::timestamp_query_count` queries, None when not requested or unsupported
+    timestamp_pool: Option<QueryPool>,
+    capture_presented_frames: bool,
+    // handed to `App::on_frame_presented` once `App::frame` returns
+    captured_frame: Option<CapturedFrame>,
+    deferred_destroy: DeferredDestroyQueue,
+    // None when RenderDoc isn't attached
+    #[cfg(feature = "renderdoc")]
+    renderdoc: Option<RenderDoc>,
+    #[cfg(feature = "renderdoc")]
+    renderdoc_capture_requested: bool,
+}
+
+impl AppContext {
+    pub fn glfw(&self) -> &Glfw {
+        &self.glfw
+    }
+
+    pub fn main_window(&self) -> &Window {
+        &self.main_window_context().window
+    }
+
+    // true while the current window is minimized, `App::frame` is not called in this state
+    pub fn is_paused(&self) -> bool {
+        self.current_window_context().paused
+    }
+
+    // creates another window with its own surface and swapchain, the returned id is passed
+    // to `App::frame` and `App::event`, the swapchain is created before its first frame
+    pub fn create_window(&mut self, width: u32, height: u32, title: &str) -> anyhow::Result<usize> {
+        let (mut window, events) = self
+            .glfw
+            .create_window(width, height, title, WindowMode::Windowed)
+            .context("failed to create window")?;
+        window.set_key_polling(true);
+        window.set_framebuffer_size_polling(true);
+
+        let surface = create_surface(self.vk.entry(), self.vk.instance(), &window, &window)?;
+        // the present queue was picked for the main window's surface
+        let present_supported = unsafe {
+            self.vk.khr_surface().get_physical_device_surface_support(
+                *self.vk.physical_device(),
+                self.vk.present_queue_family_idx(),
+                surface,
+            )
+        };
+        if !matches!(present_supported, Ok(true)) {
+            unsafe { self.vk.khr_surface().destroy_surface(surface, None) };
+            bail!("the present queue can't present to the new window's surface");
+        }
+
+        let mut window_context = WindowContext::new(window, events, surface);
+        window_context.swapchain_out_of_date = true;
+        self.windows.push(Some(window_context));
+        Ok(self.windows.len() - 1)
+    }
+
+    // None once the window was closed
+    pub fn window_context(&self, id: usize) -> Option<&WindowContext> {
+        self.windows.get(id).and_then(|e| e.as_ref())
+    }
+
+    // id of the window `App::frame` is currently running for
+    pub fn current_window(&self) -> usize {
+        self.current_window
+    }
+
+    // queues an event that is delivered to `App::event` after the current frame, as if it
+    // came from the main window
+    pub fn inject_event(&mut self, event: WindowEvent) {
+        self.injected_events.push(event);
+    }
+
+    // starts writing user input to `path` until `stop_recording_input` is called,
+    // replaces any recording in progress
+    pub fn record_input(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        self.stop_recording_input()?;
+        self.input_recorder = Some(InputRecorder::new(path.as_ref(), self.frame_idx)?);
+        Ok(())
+    }
+
+    pub fn stop_recording_input(&mut self) -> anyhow::Result<()> {
+        match self.input_recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // plays back a recording made with `record_input`, events are injected on the same frame
+    // (relative to now) they were recorded on and real user input is ignored until it ends
+    pub fn replay_input(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        self.input_replay = Some(InputReplay::new(path.as_ref(), self.frame_idx)?);
+        Ok(())
+    }
+
+    pub fn is_replaying_input(&self) -> bool {
+        self.input_replay.is_some()
+    }
+
+    // starts a debug label and a timed gpu region, regions can nest, the first region of a
+    // frame has to be begun outside of a rendering scope
+    pub fn cmd_begin_region(&mut self, cmd: CommandBuffer, name: &str) -> anyhow::Result<()> {
+        self.vk.cmd_begin_debug_label(cmd, name);
+        match &mut self.gpu_timeline {
+            Some(gpu_timeline) => {
+                gpu_timeline.begin_region(self.vk.device(), cmd, self.frame_idx, name)
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn cmd_end_region(&mut self, cmd: CommandBuffer) -> anyhow::Result<()> {
+        self.vk.cmd_end_debug_label(cmd);
+        match &mut self.gpu_timeline {
+            Some(gpu_timeline) => gpu_timeline.end_region(self.vk.device(), cmd, self.frame_idx),
+            None => Ok(()),
+        }
+    }
+
+    // gpu timings of the regions of the most recent frame whose results are available,
+    // this lags `App::max_frame_latency` frames behind the current one
+    pub fn export_frame_timeline(&self) -> Option<FrameTimeline> {
+        self.gpu_timeline
+            .as_ref()
+            .and_then(|gpu_timeline| gpu_timeline.last_completed())
+            .cloned()
+    }
+
+    // see `App::timestamp_query_count`, results are read with `vk_utils::read_timestamps`
+    pub fn timestamp_pool(&self) -> Option<QueryPool> {
+        self.timestamp_pool
+    }
+
+    // see `AssetResolver::resolve`, the search paths come from `App::asset_search_paths`
+    pub fn resolve_asset(&self, relative: &str) -> anyhow::Result<PathBuf> {
+        self.asset_resolver.resolve(relative)
+    }
+
+    pub fn asset_resolver(&self) -> &AssetResolver {
+        &self.asset_resolver
+    }
+
+    // loads a SPIR-V file found through `resolve_asset`
+    pub fn load_shader_asset(&self, relative: &str) -> anyhow::Result<ShaderModule> {
+        load_shader_module_from_file(&self.vk, &self.resolve_asset(relative)?)
+    }
+
+    // loads a texture found through `resolve_asset`, see `vk_utils::load_texture_2d`
+    pub fn load_texture_asset(&self, relative: &str) -> anyhow::Result<Texture2D> {
+        let path = self.resolve_asset(relative)?;
+        load_texture_2d(&self.vk, &path)
+    }
+
+    pub fn vk(&self) -> &Vk {
+        &self.vk
+    }
+
+    pub fn vk_mut(&mut self) -> &mut Vk {
+        &mut self.vk
+    }
+
+    // on OutOfDate the swapchain is recreated after the current frame, the frame should be
+    // skipped since neither the semaphore nor the fence will be signaled
+    pub fn acquire_next_image_from_swapchain(
+        &mut self,
+        timeout: u64,
+        semaphore: Option<&Semaphore>,
+        fence: Option<&Fence>,
+    ) -> anyhow::Result<SwapchainStatus> {
+        let swapchain = self.get_swapchain_holder()?;
+        let vk_semaphore = semaphore.map_or(Semaphore::null(), |e| e.clone());
+        let vk_fence = fence.map_or(Fence::null(), |e| e.clone());
+        let result = unsafe {
+            self.vk.khr_swapchain.acquire_next_image(
+                swapchain.swapchain,
+                timeout,
+                vk_semaphore,
+                vk_fence,
+            )
+        };
+        match result {
+            Ok((idx, false)) => Ok(SwapchainStatus::Ok(idx)),
+            Ok((idx, true)) => {
+                self.current_window_context_mut().swapchain_suboptimal = true;
+                Ok(SwapchainStatus::Suboptimal(idx))
+            }
+            Err(ash::vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.current_window_context_mut().swapchain_out_of_date = true;
+                Ok(SwapchainStatus::OutOfDate)
+            }
+            Err(err) => Err(err).context("failed to fetch next available image from swapchain"),
+        }
+    }
+
+    // the current window's swapchain reported OUT_OF_DATE or SUBOPTIMAL or a resize is waiting
+    // to settle, cleared by `recreate_swapchain`
+    pub fn swapchain_is_dirty(&self) -> bool {
+        let window = self.current_window_context();
+        window.swapchain_out_of_date || window.swapchain_suboptimal || window.resize_pending
+    }
+
+    pub fn get_swapchain_image_view(&self, idx: u32) -> anyhow::Result<&ImageView> {
+        let swapchain = self.get_swapchain_holder()?;
+        Ok(swapchain.image_views.get(idx as usize).unwrap())
+    }
+
+    // capabilities of the current window's surface, present scaling depends on `present_mode`
+    pub fn get_surface_capabilities(
+        &self,
+        present_mode: PresentModeKHR,
+    ) -> anyhow::Result<SurfaceCapabilities> {
+        self.vk
+            .query_surface_capabilities(self.current_window_context().surface, present_mode)
+    }
+
+    pub fn get_swapchain_format(&self) -> anyhow::Result<Format> {
+        Ok(self.get_swapchain_holder()?.format)
+    }
+
+    // see `App::depth_format`
+    pub fn get_swapchain_depth_format(&self) -> anyhow::Result<Option<Format>> {
+        Ok(self
+            .get_swapchain_holder()?
+            .depth
+            .as_ref()
+            .map(|e| e.format()))
+    }
+
+    // the format of the swapchain and render target views, what pipelines render to
+    pub fn get_swapchain_view_format(&self) -> anyhow::Result<Format> {
+        Ok(self.get_swapchain_holder()?.view_format)
+    }
+
+    pub fn get_swapchain_color_space(&self) -> anyhow::Result<ColorSpaceKHR> {
+        Ok(self.get_swapchain_holder()?.color_space)
+    }
+
+    // preset to render into `image_view` with `begin_rendering`, either a swapchain image
+    // or the render target, they share formats and sample count but only the render target
+    // has a view mask
+    // pipelines must be recreated if the swapchain is recreated with a different format
+    pub fn graphics_pipeline_builder(
+        &self,
+        image_view: ImageView,
+    ) -> anyhow::Result<GraphicsPipelineBuilder> {
+        Ok(GraphicsPipelineBuilder::new()
+            .render_format(
+                &[self.get_swapchain_view_format()?],
+                self.get_swapchain_depth_format()?
+                    .unwrap_or(Format::UNDEFINED),
+            )
+            .view_mask(self.rendering_view_mask(image_view))
+            .rasterization_samples(self.msaa_samples))
+    }
+
+    // the view mask `begin_rendering` uses for `image_view`
+    fn rendering_view_mask(&self, image_view: ImageView) -> u32 {
+        match &self.current_window_context().render_target {
+            Some(render_target) if render_target.image_view == image_view => self.view_mask(),
+            _ => 0,
+        }
+    }
+
+    // when false the swapchain fell back to a UNORM format and shaders writing to it
+    // directly have to encode gamma themselves
+    pub fn swapchain_is_srgb(&self) -> anyhow::Result<bool> {
+        Ok(is_srgb_format(self.get_swapchain_holder()?.view_format))
+    }
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
+    }
+
+    // shared by every window rendered in a loop, time spent with every window minimized
+    // is left out
+    pub fn delta_time(&self) -> Duration {
+        self.delta_time
+    }
+
+    // since `App::init` returned, measured at the start of the current frame loop
+    pub fn elapsed(&self) -> Duration {
+        self.last_frame_instant - self.start_instant
+    }
+
+    // averaged over the last `App::frame_stats_frame_count` frame loops
+    pub fn fps(&self) -> f32 {
+        self.frame_stats.fps()
+    }
+
+    pub fn frame_time_ms(&self) -> f32 {
+        self.frame_stats.frame_time_ms()
+    }
+
+    // e.g. 99 for the frame time 99% of the recent frames stay under
+    pub fn frame_time_percentile_ms(&self, percentile: f32) -> f32 {
+        self.frame_stats.percentile_ms(percentile)
+    }
+
+    pub fn frame_stats(&self) -> &FrameStats {
+        &self.frame_stats
+    }
+
+    // true when the process runs under RenderDoc, always false without the `renderdoc`
+    // feature
+    pub fn is_renderdoc_attached(&self) -> bool {
+        #[cfg(feature = "renderdoc")]
+        return self.renderdoc.is_some();
+        #[cfg(not(feature = "renderdoc"))]
+        false
+    }
+
+    // captures every window rendered in the next frame loop, no-op when RenderDoc isn't
+    // attached or without the `renderdoc` feature
+    pub fn trigger_renderdoc_capture(&mut self) {
+        #[cfg(feature = "renderdoc")]
+        {
+            self.renderdoc_capture_requested = self.renderdoc.is_some();
+        }
+    }
+
+    fn begin_renderdoc_capture(&mut self) {
+        #[cfg(feature = "renderdoc")]
+        if let Some(renderdoc) = &self.renderdoc {
+            if std::mem::take(&mut self.renderdoc_capture_requested) {
+                renderdoc.start_frame_capture();
+            }
+        }
+    }
+
+    fn end_renderdoc_capture(&mut self) {
+        #[cfg(feature = "renderdoc")]
+        if let Some(renderdoc) = &self.renderdoc {
+            if renderdoc.is_frame_capturing() && !renderdoc.end_frame_capture() {
+                log::warn!("RenderDoc failed to capture the frame");
+            }
+        }
+    }
+
+    // the main window's title is set at most once per second
+    fn update_frame_stats_title(&mut self) {
+        let Some(title) = &self.frame_stats_title else {
+            return;
+        };
+        if self.last_frame_instant - self.last_title_update < Duration::from_secs(1) {
+            return;
+        }
+        self.last_title_update = self.last_frame_instant;
+        let title = format!(
+            "{title} - {:.0} fps, {:.2} ms (p99 {:.2} ms)",
+            self.frame_stats.fps(),
+            self.frame_stats.frame_time_ms(),
+            self.frame_stats.percentile_ms(99.0)
+        );
+        self.window_context_mut(0).window.set_title(&title);
+    }
+
+    // views rendered by a single draw, see `App::multiview_count`
+    pub fn multiview_count(&self) -> u32 {
+        self.multiview_count
+    }
+
+    // samples per pixel of the swapchain attachments, see `App::msaa_samples`
+    pub fn msaa_samples(&self) -> SampleCountFlags {
+        self.msaa_samples
+    }
+
+    // a bit per view for `RenderingInfo` and pipelines, 0 without multiview
+    pub fn view_mask(&self) -> u32 {
+        if self.multiview_count > 1 {
+            u32::MAX >> (32 - self.multiview_count)
+        } else {
+            0
+        }
+    }
+
+    // recreates the internal render targets of all windows if the scale changed
+    pub fn set_render_scale(&mut self, render_scale: f32) -> anyhow::Result<()> {
+        if render_scale <= 0.0 {
+            bail!("render scale must be positive, got {render_scale}");
+        }
+        if render_scale == self.render_scale {
+            return Ok(());
+        }
+
+        self.render_scale = render_scale;
+        for id in 0..self.windows.len() {
+            if self
+                .window_context(id)
+                .is_some_and(|e| e.swapchain.is_some())
+            {
+                self.recreate_render_target(id)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // false at a render scale of 1 without multiview, the app then renders straight into the
+    // swapchain image
+    pub fn has_render_target(&self) -> bool {
+        self.current_window_context().render_target.is_some()
+    }
+
+    // the image the app should render into, sized `swapchain extent * render scale`
+    // expected to be in COLOR_ATTACHMENT_OPTIMAL when blitting, see `has_render_target`
+    pub fn get_render_target_image_view(&self) -> anyhow::Result<&ImageView> {
+        Ok(&self.get_render_target()?.image_view)
+    }
+
+    pub fn get_render_target_extent(&self) -> anyhow::Result<Extent2D> {
+        Ok(self.get_render_target()?.extent)
+    }
+
+    // records a blit of the render target into the given swapchain image,
+    // leaving the render target in TRANSFER_SRC_OPTIMAL and the swapchain image in PRESENT_SRC_KHR
+    // with multiview the views are placed side by side
+    // without a render target the swapchain image was rendered to directly and is only
+    // transitioned from COLOR_ATTACHMENT_OPTIMAL to PRESENT_SRC_KHR
+    pub fn cmd_blit_render_target_to_swapchain(
+        &self,
+        cmd: CommandBuffer,
+        swapchain_image_idx: u32,
+    ) -> anyhow::Result<()> {
+        let swapchain = self.get_swapchain_holder()?;
+        let swapchain_image = *swapchain
+            .images
+            .get(swapchain_image_idx as usize)
+            .context("invalid swapchain image index")?;
+        let device = self.vk.device();
+        let Some(render_target) = &self.current_window_context().render_target else {
+            let present_barrier = ImageMemoryBarrier::builder()
+                .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(AccessFlags::empty())
+                .old_layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .new_layout(ImageLayout::PRESENT_SRC_KHR)
+                .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+                .image(swapchain_image)
+                .subresource_range(color_subresource_range())
+                .build();
+            unsafe {
+                device.cmd_pipeline_barrier(
+                    cmd,
+                    PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                    PipelineStageFlags::BOTTOM_OF_PIPE,
+                    DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[present_barrier],
+                );
+            }
+            return Ok(());
+        };
+
+        let pre_blit_barriers = [
+            ImageMemoryBarrier::builder()
+                .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(AccessFlags::TRANSFER_READ)
+                .old_layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .new_layout(ImageLayout::TRANSFER_SRC_OPTIMAL)
+                .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+                .image(render_target.image)
+                .subresource_range(ImageSubresourceRange {
+                    layer_count: render_target.layers,
+                    ..color_subresource_range()
+                })
+                .build(),
+            ImageMemoryBarrier::builder()
+                .src_access_mask(AccessFlags::empty())
+                .dst_access_mask(AccessFlags::TRANSFER_WRITE)
+                .old_layout(ImageLayout::UNDEFINED)
+                .new_layout(ImageLayout::TRANSFER_DST_OPTIMAL)
+                .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+                .image(swapchain_image)
+                .subresource_range(color_subresource_range())
+                .build(),
+        ];
+
+        let subresource = ImageSubresourceLayers::builder()
+            .aspect_mask(ImageAspectFlags::COLOR)
+            .layer_count(1)
+            .build();
+        let dst_max = extent_to_offset(swapchain.extent);
+        let dst_x = |layer: u32| {
+            (swapchain.extent.width as u64 * layer as u64 / render_target.layers as u64) as i32
+        };
+        let blits: Vec<_> = (0..render_target.layers)
+            .map(|layer| {
+                ImageBlit::builder()
+                    .src_subresource(ImageSubresourceLayers {
+                        base_array_layer: layer,
+                        ..subresource
+                    })
+                    .src_offsets([Offset3D::default(), extent_to_offset(render_target.extent)])
+                    .dst_subresource(subresource)
+                    .dst_offsets([
+                        Offset3D {
+                            x: dst_x(layer),
+                            ..Offset3D::default()
+                        },
+                        Offset3D {
+                            x: dst_x(layer + 1),
+                            ..dst_max
+                        },
+                    ])
+                    .build()
+            })
+            .collect();
+
+        let post_blit_barrier = ImageMemoryBarrier::builder()
+            .src_access_mask(AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(AccessFlags::empty())
+            .old_layout(ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(ImageLayout::PRESENT_SRC_KHR)
+            .src_queue_family_index(QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(QUEUE_FAMILY_IGNORED)
+            .image(swapchain_image)
+            .subresource_range(color_subresource_range())
+            .build();
+
+        unsafe {
+            device.cmd_pipeline_barrier(
+                cmd,
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                PipelineStageFlags::TRANSFER,
+                DependencyFlags::empty(),
+                &[],
+                &[],
+                &pre_blit_barriers,
+            );
+            device.cmd_blit_image(
+                cmd,
+                render_target.image,
+                ImageLayout::TRANSFER_SRC_OPTIMAL,
+                swapchain_image,
+                ImageLayout::TRANSFER_DST_OPTIMAL,
+                &blits,
+                Filter::LINEAR,
+            );
+            device.cmd_pipeline_barrier(
+                cmd,
+                PipelineStageFlags::TRANSFER,
+                PipelineStageFlags::BOTTOM_OF_PIPE,
+                DependencyFlags::empty(),
+                &[],
+                &[],
+                &[post_blit_barrier],
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frame_latency_fences.len()
+    }
+
+    // index of the current frame among the frames in flight, for per frame resources
+    pub fn frame_slot(&self) -> usize {
+        (self.frame_idx % self.frame_latency_fences.len() as u64) as usize
+    }
+
+    // the last queue submit of the current frame, signals the frame's latency fence which
+    // keeps at most `App::max_frame_latency` frames in flight
+    // the fence is only reset right before it is submitted, a frame that never submits
+    // leaves it signaled so waiting on it can't block forever
+    pub fn submit_frame(&mut self, submits: &[SubmitInfo]) -> anyhow::Result<()> {
+        let fence = self.frame_latency_fences[self.frame_slot()];
+        unsafe {
+            self.vk
+                .device()
+                .reset_fences(&[fence])
+                .context("failed to reset frame latency fence")?;
+            self.vk
+                .device()
+                .queue_submit(*self.vk.queue(), submits, fence)
+                .context("failed to submit frame")
+        }
+    }
+
+    // returns false if `timeout` elapsed before the fence was signaled
+    pub fn wait_for_fence(&self, fence: Fence, timeout: Duration) -> anyhow::Result<bool> {
+        let timeout = u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX);
+        match unsafe { self.vk.device().wait_for_fences(&[fence], true, timeout) } {
+            Ok(()) => Ok(true),
+            Err(ash::vk::Result::TIMEOUT) => Ok(false),
+            Err(ash::vk::Result::ERROR_DEVICE_LOST) => {
+                Err(DeviceLost).context("failed to wait for fence")
+            }
+            Err(err) => Err(err).context("failed to wait for fence"),
+        }
+    }
+
+    pub fn reset_fence(&self, fence: Fence) -> anyhow::Result<()> {
+        unsafe {
+            self.vk
+                .device()
+                .reset_fences(&[fence])
+                .context("failed to reset fence")
+        }
+    }
+
+    // presents the swapchain image on the present queue, tags it with a present id when
+    // VK_KHR_present_wait is available so frame latency can be limited on the presentation side
+    // OutOfDate schedules a swapchain recreation after the current frame
+    pub fn present(
+        &mut self,
+        image_idx: u32,
+        wait_semaphores: &[Semaphore],
+    ) -> anyhow::Result<PresentResult> {
+        if self.capture_presented_frames && self.current_window == 0 {
+            self.capture_presented_frame(image_idx)?;
+        }
+
+        let window = self.current_window_context();
+        let swapchain = window.swapchain_holder()?;
+        let swapchains = [swapchain.swapchain];
+        let image_indices = [image_idx];
+        let present_ids = [window.present_id + 1];
+        let mut present_id_info = PresentIdKHR::builder().present_ids(&present_ids).build();
+        let mut present_info = PresentInfoKHR::builder()
+            .wait_semaphores(wait_semaphores)
+            .swapchains(&swapchains)
+            .image_indices(&image_indices);
+        if self.vk.khr_present_wait.is_some() {
+            present_info = present_info.push_next(&mut present_id_info);
+        }
+
+        let result = unsafe {
+            self.vk
+                .khr_swapchain()
+                .queue_present(*self.vk.present_queue(), &present_info)
+        };
+        let present_result = match result {
+            Ok(false) => PresentResult::Ok,
+            Ok(true) => {
+                self.current_window_context_mut().swapchain_suboptimal = true;
+                PresentResult::Suboptimal
+            }
+            Err(ash::vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.current_window_context_mut().swapchain_out_of_date = true;
+                return Ok(PresentResult::OutOfDate);
+            }
+            Err(err) => return Err(err).context("failed to present swapchain image"),
+        };
+        self.current_window_context_mut().present_id += 1;
+
+        Ok(present_result)
+    }
+
+    // reads swapchain image `image_index` of the current window back as tightly packed RGBA8
+    // rows, sRGB encoded when the swapchain is, blocks until the copy is done
+    // the image must have been rendered and transitioned to PRESENT_SRC_KHR by submitted
+    // commands but not presented yet, presented images belong to the presentation engine
+    pub fn capture_frame(
+        &mut self,
+        image_index: usize,
+        cmd_pool: CommandPool,
+    ) -> anyhow::Result<Vec<u8>> {
+        let swapchain = self.get_swapchain_holder()?;
+        if !swapchain
+            .image_usage
+            .contains(ImageUsageFlags::TRANSFER_SRC)
+        {
+            bail!("the surface doesn't support reading swapchain images back");
+        }
+        let image = *swapchain
+            .images
+            .get(image_index)
+            .with_context(|| format!("swapchain image {image_index} doesn't exist"))?;
+        let (format, extent) = (swapchain.format, swapchain.extent);
+
+        readback_image(
+            &mut self.vk,
+            cmd_pool,
+            image,
+            format,
+            extent,
+            ImageLayout::PRESENT_SRC_KHR,
+        )
+    }
+
+    // reads back the swapchain image about to be presented, the readback is submitted to
+    // the queue the frame was rendered on so it's ordered after the frame's commands, and
+    // blocks on its own fence until the copy is done, so capturing stalls the frame
+    fn capture_presented_frame(&mut self, image_idx: u32) -> anyhow::Result<()> {
+        let cmd_pool = self.vk.immediate_command_pool;
+        let pixels = self.capture_frame(image_idx as usize, cmd_pool)?;
+        let swapchain = self.get_swapchain_holder()?;
+        let (format, extent) = (swapchain.format, swapchain.extent);
+        let format = if is_srgb_format(format) {
+            Format::R8G8B8A8_SRGB
+        } else {
+            Format::R8G8B8A8_UNORM
+        };
+        self.captured_frame = Some(CapturedFrame {
+            pixels,
+            extent,
+            format,
+        });
+
+        Ok(())
+    }
+
+    // blocks until starting a new frame keeps the number of in-flight frames within the limit
+    fn wait_for_frame_latency(&mut self) -> anyhow::Result<()> {
+        let max_frame_latency = self.frame_latency_fences.len() as u64;
+
+        let window = self.current_window_context();
+        if let (Some(khr_present_wait), Some(swapchain)) =
+            (self.vk.khr_present_wait.as_ref(), window.swapchain.as_ref())
+        {
+            if window.present_id > max_frame_latency {
+                unsafe {
+                    khr_present_wait
+                        .wait_for_present(
+                            swapchain.swapchain,
+                            window.present_id - max_frame_latency,
+                            u64::MAX,
+                        )
+                        .context("failed to wait for present")?
+                };
+            }
+        }
+
+        // fences of frames that never called `submit_frame` stay signaled, so this never
+        // blocks forever
+        let fence = self.frame_latency_fences[(self.frame_idx % max_frame_latency) as usize];
+        unsafe {
+            self.vk
+                .device()
+                .wait_for_fences(&[fence], true, u64::MAX)
+                .context("failed to wait for frame latency fence")?
+        };
+
+        // the fence covered the frame `max_frame_latency` frames ago
+        let frame_slot = self.frame_slot();
+        self.draw_2d.begin_frame(&mut self.vk, frame_slot)?;
+        self.deferred_destroy
+            .destroy_expired(&mut self.vk, self.frame_idx, max_frame_latency)
+    }
+
+    // waits for every frame in flight and then for the whole device, afterwards no gpu work
+    // references resources owned by the context or the app, also runs deferred destroys
+    pub fn drain(&mut self) -> anyhow::Result<()> {
+        unsafe {
+            self.vk
+                .device()
+                .wait_for_fences(&self.frame_latency_fences, true, u64::MAX)
+                .context("failed to wait for frame latency fences")?;
+            self.vk
+                .device()
+                .device_wait_idle()
+                .context("failed to wait for device idle")?;
+        }
+        self.deferred_destroy.destroy_all(&mut self.vk)
+    }
+
+    // destroys the resource once the command buffers of the current frame can no longer
+    // reference it, the app must submit with `submit_frame` for this to be safe
+    pub fn destroy_later(&mut self, resource: impl Into<DeferredResource>) {
+        self.deferred_destroy.push(self.frame_idx, resource.into());
+    }
+
+    // immediate mode debug drawing in pixel coordinates (origin top left),
+    // primitives accumulate until `cmd_flush_draw_2d` is called, nothing is flushed
+    // automatically and unflushed primitives are dropped when the next frame starts, so
+    // they have to be drawn and flushed inside `App::frame`
+    pub fn draw_rect(&mut self, min: [f32; 2], max: [f32; 2], color: [f32; 4]) {
+        self.draw_2d.rect(min, max, color);
+    }
+
+    pub fn draw_line_2d(&mut self, a: [f32; 2], b: [f32; 2], color: [f32; 4]) {
+        self.draw_2d.line(a, b, color);
+    }
+
+    pub fn draw_circle(&mut self, center: [f32; 2], radius: f32, color: [f32; 4]) {
+        self.draw_2d.circle(center, radius, color);
+    }
+
+    // records the 2d primitives accumulated this frame, must be called inside the rendering
+    // scope `begin_rendering` began with `image_view` and `extent`
+    pub fn cmd_flush_draw_2d(
+        &mut self,
+        cmd: CommandBuffer,
+        image_view: ImageView,
+        extent: Extent2D,
+    ) -> anyhow::Result<()> {
+        let swapchain = self.get_swapchain_holder()?;
+        // the same attachments `begin_rendering` attaches for `extent`
+        let samples = if swapchain
+            .msaa_color
+            .as_ref()
+            .is_some_and(|msaa_color| attachment_fits(msaa_color, extent))
+        {
+            self.msaa_samples
+        } else {
+            SampleCountFlags::TYPE_1
+        };
+        let depth_format = swapchain
+            .depth
+            .as_ref()
+            .filter(|depth| attachment_fits(depth, extent))
+            .map_or(Format::UNDEFINED, |depth| depth.format());
+        let target = Draw2dTarget {
+            color_format: swapchain.view_format,
+            depth_format,
+            samples,
+            view_mask: self.rendering_view_mask(image_view),
+        };
+        let frame_slot = self.frame_slot();
+        self.draw_2d
+            .flush(&mut self.vk, cmd, frame_slot, target, extent)
+    }
+
+    // `data` is checked against the ranges the layout was created with in debug builds
+    pub fn push_constants(
+        &self,
+        cmd: CommandBuffer,
+        layout: PipelineLayout,
+        stages: ShaderStageFlags,
+        offset: u32,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        #[cfg(debug_assertions)]
+        self.vk
+            .validate_push_constants(layout, stages, offset, data.len() as u32)?;
+        unsafe {
+            self.vk
+                .device()
+                .cmd_push_constants(cmd, layout, stages, offset, data)
+        };
+        Ok(())
     }
 
-    pub fn entry(&self) -> &Entry {
-        &self.entry
+    // with a copy per frame in flight, update the one of `frame_slot` each frame
+    pub fn create_uniform_buffer<T: Copy>(&mut self) -> anyhow::Result<UniformBuffer<T>> {
+        let frames_in_flight = self.frames_in_flight();
+        UniformBuffer::new(&mut self.vk, frames_in_flight)
     }
 
-    pub fn khr_surface(&self) -> &ash::extensions::khr::Surface {
-        &self.khr_surface
+    // creates a depth/msaa style attachment, transient when `App::use_transient_attachments` is set
+    // with multiview it gets a layer per view like the render target
+    pub fn create_attachment_image(
+        &mut self,
+        extent: Extent2D,
+        format: Format,
+        usage: ImageUsageFlags,
+        samples: SampleCountFlags,
+        aspect_mask: ImageAspectFlags,
+    ) -> anyhow::Result<AttachmentImage> {
+        AttachmentImage::new(
+            &mut self.vk,
+            extent,
+            format,
+            usage,
+            samples,
+            aspect_mask,
+            self.multiview_count,
+            self.use_transient_attachments,
+        )
     }
 
-    pub fn khr_swapchain(&self) -> &ash::extensions::khr::Swapchain {
-        &self.khr_swapchain
+    // begins rendering into a single color attachment that is cleared on load, the image
+    // must be in COLOR_ATTACHMENT_OPTIMAL, the depth attachment of `App::depth_format` is
+    // attached and cleared to 1.0 as well
+    // with `App::msaa_samples` rendering goes to a multisampled image resolved into `image_view`
+    // `clear_color` is linear sRGB and encoded for the swapchain's format and color space
+    // rendering into the render target uses `view_mask`, only it has a layer per view
+    pub fn begin_rendering(
+        &self,
+        cmd: CommandBuffer,
+        image_view: ImageView,
+        clear_color: [f32; 4],
+        extent: Extent2D,
+    ) {
+        // the image view is either the swapchain or the render target, which share a format
+        let window = self.current_window_context();
+        let clear_color = match &window.swapchain {
+            Some(swapchain) => {
+                encode_for_swapchain(clear_color, swapchain.view_format, swapchain.color_space)
+            }
+            None => clear_color,
+        };
+        // like the depth attachment, the multisampled image fits both render sizes
+        let fits = |attachment: &&AttachmentImage| attachment_fits(attachment, extent);
+        let swapchain = window.swapchain.as_ref();
+        let msaa_color = swapchain.and_then(|e| e.msaa_color.as_ref()).filter(fits);
+        let color_attachment = RenderingAttachmentInfoKHR::builder()
+            .image_layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(AttachmentLoadOp::CLEAR)
+            .clear_value(ClearValue {
+                color: ClearColorValue {
+                    float32: clear_color,
+                },
+            });
+        let color_attachment = match msaa_color {
+            Some(msaa_color) => {
+                // the previous frame's resolve must be done before it is cleared
+                image_barrier(
+                    &self.vk,
+                    cmd,
+                    msaa_color.image(),
+                    ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    ImageAspectFlags::COLOR,
+                );
+                color_attachment
+                    .image_view(msaa_color.view())
+                    .store_op(AttachmentStoreOp::DONT_CARE)
+                    .resolve_mode(ResolveModeFlags::AVERAGE)
+                    .resolve_image_view(image_view)
+                    .resolve_image_layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            }
+            None => color_attachment
+                .image_view(image_view)
+                .store_op(AttachmentStoreOp::STORE),
+        };
+        let color_attachments = [color_attachment.build()];
+        let view_mask = self.rendering_view_mask(image_view);
+        // the depth attachment is sized to fit both the swapchain and the render target
+        let depth = swapchain.and_then(|e| e.depth.as_ref()).filter(fits);
+        let depth_attachment = depth.map(|depth| {
+            // the previous frame's depth tests must be done before it is cleared
+            image_barrier(
+                &self.vk,
+                cmd,
+                depth.image(),
+                DEPTH_STENCIL_ATTACHMENT_LAYOUT,
+                DEPTH_STENCIL_ATTACHMENT_LAYOUT,
+                depth_stencil_aspect_mask(depth.format()),
+            );
+            // a transient depth attachment never outlives the pass, so it isn't stored
+            let store_op = if self.use_transient_attachments {
+                AttachmentStoreOp::DONT_CARE
+            } else {
+                AttachmentStoreOp::STORE
+            };
+            depth_stencil_attachment_info(depth.view(), 1.0, self.stencil_clear_value, store_op)
+        });
+        let mut rendering_info = RenderingInfoKHR::builder()
+            .render_area(Rect2D {
+                offset: Offset2D::default(),
+                extent,
+            })
+            .layer_count(1)
+            .view_mask(view_mask)
+            .color_attachments(&color_attachments);
+        if let Some(depth_attachment) = &depth_attachment {
+            rendering_info = rendering_info.depth_attachment(depth_attachment);
+            if depth.is_some_and(|e| format_has_stencil(e.format())) {
+                rendering_info = rendering_info.stencil_attachment(depth_attachment);
+            }
+        }
+        let rendering_info = rendering_info.build();
+        self.vk.cmd_begin_rendering(cmd, &rendering_info);
     }
 
-    pub fn instance(&self) -> &Instance {
-        &self.instance
+    pub fn end_rendering(&self, cmd: CommandBuffer) {
+        self.vk.cmd_end_rendering(cmd);
     }
 
-    pub fn physical_device(&self) -> &PhysicalDevice {
-        &self.physical_device
+    // clears depth to `clear_depth` and, for formats with a stencil aspect, stencil to
+    // `App::stencil_clear_value`, the image must be in
+    // `vk_utils::DEPTH_STENCIL_ATTACHMENT_LAYOUT`, pass DONT_CARE as `store_op` for transient
+    // attachments
+    pub fn depth_stencil_attachment_info(
+        &self,
+        view: ImageView,
+        clear_depth: f32,
+        store_op: AttachmentStoreOp,
+    ) -> RenderingAttachmentInfoKHR {
+        depth_stencil_attachment_info(view, clear_depth, self.stencil_clear_value, store_op)
     }
 
-    pub fn queue_family_idx(&self) -> u32 {
-        self.queue_family_idx
+    fn main_window_context(&self) -> &WindowContext {
+        // the main window is only closed by ending `run`
+        self.windows[0].as_ref().unwrap()
     }
 
-    pub fn device(&self) -> &Device {
-        &self.device
+    fn current_window_context(&self) -> &WindowContext {
+        self.windows[self.current_window].as_ref().unwrap()
     }
 
-    pub fn queue(&self) -> &Queue {
-        &self.queue
+    fn current_window_context_mut(&mut self) -> &mut WindowContext {
+        self.window_context_mut(self.current_window)
     }
-}
 
-impl Drop for Vk {
-    fn drop(&mut self) {
-        unsafe {
-            self.device.destroy_device(None);
-            ManuallyDrop::drop(&mut self.physical_device);
-            self.instance.destroy_instance(None);
-            ManuallyDrop::drop(&mut self.entry);
-        }
+    // panics for closed windows, only used with ids of open ones
+    fn window_context_mut(&mut self, id: usize) -> &mut WindowContext {
+        self.windows[id].as_mut().unwrap()
     }
-}
 
-pub struct AppContext {
-    glfw: Glfw,
-    main_window: Window,
-    main_surface: SurfaceKHR,
-    vk: Vk,
-    swapchain: Option<SwapchainHolder>,
-}
+    fn get_render_target(&self) -> anyhow::Result<&RenderTarget> {
+        self.current_window_context().render_target()
+    }
 
-impl AppContext {
-    pub fn glfw(&self) -> &Glfw {
-        &self.glfw
+    fn scaled_extent(&self, extent: Extent2D) -> Extent2D {
+        Extent2D {
+            width: ((extent.width as f32 * self.render_scale).round() as u32).max(1),
+            height: ((extent.height as f32 * self.render_scale).round() as u32).max(1),
+        }
     }
 
-    pub fn main_window(&self) -> &Window {
-        &self.main_window
+    // only needed when views have to be combined or the resolution differs from the swapchain
+    fn needs_render_target(&self) -> bool {
+        self.render_scale != 1.0 || self.multiview_count > 1
     }
 
-    // fails if swapchain is OUT_OF_DATE or SUBOPTIMAL
-    // which is unlikely since we are already explicitly handling framebuffer resizes
-    pub fn acquire_next_image_from_swapchain(
-        &mut self,
-        timeout: u64,
-        semaphore: Option<&Semaphore>,
-        fence: Option<&Fence>,
-    ) -> anyhow::Result<u32> {
-        let swapchain = self.get_swapchain_holder()?;
-        let vk_semaphore = semaphore.map_or(Semaphore::null(), |e| e.clone());
-        let vk_fence = fence.map_or(Fence::null(), |e| e.clone());
-        unsafe {
-            self.vk
-                .khr_swapchain
-                .acquire_next_image(swapchain.swapchain, timeout, vk_semaphore, vk_fence)
-                .map(|e| e.0)
-                .context("failed to fetch next available image from swapchain")
+    fn recreate_render_target(&mut self, id: usize) -> anyhow::Result<()> {
+        let (format, view_format, extent, image_usage) = {
+            let swapchain = self.window_context_mut(id).swapchain_holder()?;
+            (
+                swapchain.format,
+                swapchain.view_format,
+                swapchain.extent,
+                swapchain.image_usage,
+            )
+        };
+
+        self.destroy_render_target(id)?;
+        if !self.needs_render_target() {
+            return Ok(());
+        }
+        if !image_usage.contains(ImageUsageFlags::TRANSFER_DST) {
+            bail!("the surface doesn't support blitting a scaled or multiview render target");
         }
+
+        let extent = self.scaled_extent(extent);
+        let render_target = RenderTarget::new(
+            &mut self.vk,
+            format,
+            view_format,
+            extent,
+            self.multiview_count,
+        )?;
+        self.window_context_mut(id).render_target = Some(render_target);
+
+        Ok(())
     }
 
-    pub fn get_swapchain_image_view(&self, idx: u32) -> anyhow::Result<&ImageView> {
-        let swapchain = self.get_swapchain_holder()?;
-        Ok(swapchain.image_views.get(idx as usize).unwrap())
+    fn destroy_render_target(&mut self, id: usize) -> anyhow::Result<()> {
+        if let Some(old_render_target) = self.window_context_mut(id).render_target.take() {
+            // the old target may still be read by an in-flight blit
+            unsafe {
+                self.vk
+                    .device()
+                    .device_wait_idle()
+                    .context("failed to wait for device idle")?
+            };
+            old_render_target.destroy(&mut self.vk)?;
+        }
+
+        Ok(())
     }
 
     fn get_swapchain_holder(&self) -> anyhow::Result<&SwapchainHolder> {
-        self.swapchain
-            .as_ref()
-            .context("swapchain not initialized!")
+        self.current_window_context().swapchain_holder()
     }
 
-    fn recreate_swapchain(&mut self, app: &impl App) -> anyhow::Result<()> {
-        if let Some(old_swapchain) = self.swapchain.take() {
-            old_swapchain.destroy(&self.vk);
+    // out of date swapchains and settled resizes are handled before the next frame, apps
+    // polling `swapchain_is_dirty` can recreate right away, e.g. on SUBOPTIMAL, `app` is
+    // the app `run` was called with, usually `self` inside a hook
+    pub fn recreate_swapchain(&mut self, app: &mut impl App, id: usize) -> anyhow::Result<()> {
+        let window = self
+            .windows
+            .get_mut(id)
+            .and_then(Option::as_mut)
+            .with_context(|| format!("window {id} doesn't exist"))?;
+        window.swapchain_out_of_date = false;
+        window.swapchain_suboptimal = false;
+        window.resize_pending = false;
+        if let Some(old_swapchain) = window.swapchain.take() {
+            // the image views and depth attachment may still be in use
+            unsafe {
+                self.vk
+                    .device()
+                    .device_wait_idle()
+                    .context("failed to wait for device idle")?
+            };
+            old_swapchain.destroy(&mut self.vk);
         }
 
-        let (width, height) = self.main_window.get_framebuffer_size();
-        let swapchain = create_swapchain(
-            &self.vk,
-            &self.main_surface,
+        let (width, height) = window.window.get_framebuffer_size();
+        // a minimized window has a zero sized framebuffer, a swapchain can't be created for it
+        window.paused = width <= 0 || height <= 0;
+        if window.paused {
+            return self.destroy_render_target(id);
+        }
+        let surface = window.surface;
+
+        // the app's format is only a hint, fall back to what the surface supports
+        let preferred_formats: Vec<_> = [(
             app.get_swapchain_format()?,
             app.get_swapchain_color_space()?,
-            ImageUsageFlags::COLOR_ATTACHMENT,
-            Extent2D::builder()
-                .width(width as u32)
-                .height(height as u32)
-                .build(),
+        )]
+        .into_iter()
+        .chain(app.get_swapchain_fallback_formats())
+        .collect();
+        let surface_format = choose_surface_format(
+            self.vk.khr_surface(),
+            *self.vk.physical_device(),
+            surface,
+            &preferred_formats,
+        )?;
+        let view_format = app.swapchain_view_format().unwrap_or(surface_format.format);
+        if !is_srgb_format(view_format) {
+            log::info!(
+                "swapchain view format {view_format:?} is not sRGB, shaders must apply gamma manually"
+            );
+        }
+
+        let extent = Extent2D::builder()
+            .width(width as u32)
+            .height(height as u32)
+            .build();
+        let scaled_extent = self.scaled_extent(extent);
+        let attachments = SwapchainAttachmentsDesc {
+            depth_format: app.depth_format(),
+            samples: self.msaa_samples,
+            extent: Extent2D {
+                width: extent.width.max(scaled_extent.width),
+                height: extent.height.max(scaled_extent.height),
+            },
+            layers: self.multiview_count,
+            transient: self.use_transient_attachments,
+        };
+        let swapchain = create_swapchain(
+            &mut self.vk,
+            &surface,
+            surface_format.format,
+            view_format,
+            surface_format.color_space,
+            app.get_swapchain_present_mode()?,
+            // TRANSFER_DST is needed to blit the render target, TRANSFER_SRC to read the
+            // images back, both are dropped when unsupported
+            ImageUsageFlags::COLOR_ATTACHMENT
+                | ImageUsageFlags::TRANSFER_DST
+                | ImageUsageFlags::TRANSFER_SRC,
+            extent,
             app.get_swapchain_min_image_count()?,
+            app.present_scaling(),
+            attachments,
         )?;
 
-        self.swapchain = Some(swapchain);
+        let extent = swapchain.extent;
+        let image_count = swapchain.images.len();
+        let window = self.window_context_mut(id);
+        window.swapchain = Some(swapchain);
+        // present ids are per swapchain
+        window.present_id = 0;
+
+        self.recreate_render_target(id)?;
+        // the hook sees the recreated window as current, callers may be in the middle of
+        // another window's frame
+        let current_window = std::mem::replace(&mut self.current_window, id);
+        let result = app.on_swapchain_recreated(self, id, extent, image_count);
+        self.current_window = current_window;
+        result
+    }
+
+    // destroys secondary windows whose close was requested, the main window ends `run`
+    fn close_requested_windows(&mut self) -> anyhow::Result<()> {
+        for id in 1..self.windows.len() {
+            if !self
+                .window_context(id)
+                .is_some_and(|e| e.window.should_close())
+            {
+                continue;
+            }
+
+            // the swapchain images may still be in use
+            unsafe {
+                self.vk
+                    .device()
+                    .device_wait_idle()
+                    .context("failed to wait for device idle")?
+            };
+            if let Some(window) = self.windows[id].take() {
+                window.destroy(&mut self.vk);
+            }
+            if self.current_window == id {
+                self.current_window = 0;
+            }
+        }
 
         Ok(())
     }
@@ -200,14 +2642,44 @@ impl AppContext {
 impl Drop for AppContext {
     fn drop(&mut self) {
         unsafe {
-            if let Some(swapchain) = self.swapchain.take() {
-                swapchain.destroy(&self.vk);
+            // make sure the gpu is done with the swapchain images before destroying them
+            // we may be unwinding here, so just report the failure instead of panicking
+            if let Err(err) = self.vk.device().device_wait_idle() {
+                log::error!("failed to wait for device idle: {err}");
+            }
+
+            if let Err(err) = self.deferred_destroy.destroy_all(&mut self.vk) {
+                log::error!("failed to destroy deferred resources: {err}");
+            }
+
+            if let Err(err) = self.draw_2d.destroy(&mut self.vk) {
+                log::error!("failed to destroy 2d drawing resources: {err}");
+            }
+            if let Some(mut gpu_timeline) = self.gpu_timeline.take() {
+                gpu_timeline.destroy(self.vk.device());
+            }
+            if let Some(timestamp_pool) = self.timestamp_pool.take() {
+                self.vk.device().destroy_query_pool(timestamp_pool, None);
+            }
+            for window in self.windows.drain(..).flatten() {
+                window.destroy(&mut self.vk);
+            }
+            for fence in self.frame_latency_fences.drain(..) {
+                self.vk.device().destroy_fence(fence, None);
             }
-            self.vk.khr_surface.destroy_surface(self.main_surface, None);
         }
     }
 }
 
+// how swapchain images are presented to a surface of a different size,
+// empty gravity flags leave the placement to the presentation engine
+#[derive(Clone, Copy, Debug)]
+pub struct PresentScaling {
+    pub scaling: PresentScalingFlagsEXT,
+    pub gravity_x: PresentGravityFlagsEXT,
+    pub gravity_y: PresentGravityFlagsEXT,
+}
+
 pub trait App {
     fn should_auto_close(&self) -> bool {
         true
@@ -225,17 +2697,222 @@ pub trait App {
         Ok(ColorSpaceKHR::SRGB_NONLINEAR)
     }
 
+    // tried in order when the surface doesn't support the preferred format, some surfaces
+    // only offer UNORM formats, check `AppContext::swapchain_is_srgb` to know which was picked
+    fn get_swapchain_fallback_formats(&self) -> Vec<(Format, ColorSpaceKHR)> {
+        vec![
+            (Format::B8G8R8A8_SRGB, ColorSpaceKHR::SRGB_NONLINEAR),
+            (Format::R8G8B8A8_SRGB, ColorSpaceKHR::SRGB_NONLINEAR),
+            (Format::B8G8R8A8_UNORM, ColorSpaceKHR::SRGB_NONLINEAR),
+            (Format::R8G8B8A8_UNORM, ColorSpaceKHR::SRGB_NONLINEAR),
+        ]
+    }
+
+    // extensions enabled on top of the ones required by the window system,
+    // unavailable ones are skipped with a warning
+    fn additional_instance_extensions(&self) -> Vec<CString> {
+        vec![]
+    }
+
+    // layers enabled next to the validation layer (e.g. VK_LAYER_LUNARG_api_dump), also
+    // without the `validation_layers` feature, unavailable ones are skipped with a warning
+    fn additional_instance_layers(&self) -> Vec<CString> {
+        vec![]
+    }
+
+    // enabled next to the runtime's own device extensions (swapchain, dynamic rendering and
+    // portability subset), devices lacking any of them are not selected
+    fn required_device_extensions(&self) -> Vec<CString> {
+        vec![]
+    }
+
+    // at least 1.1, devices that don't support it are skipped, with 1.3 dynamic rendering is
+    // used from core instead of VK_KHR_dynamic_rendering
+    fn api_version(&self) -> u32 {
+        ash::vk::API_VERSION_1_2
+    }
+
+    // falls back to FIFO if the requested mode is not supported by the surface
+    fn get_swapchain_present_mode(&self) -> anyhow::Result<PresentModeKHR> {
+        Ok(PresentModeKHR::FIFO)
+    }
+
+    // the internal render target is sized `swapchain extent * render scale`,
+    // values below 1 trade quality for performance, values above 1 supersample
+    // at 1 there is no render target unless multiview needs one, see
+    // `AppContext::has_render_target`
+    fn render_scale(&self) -> f32 {
+        1.0
+    }
+
+    // above 1 every draw into the render target is broadcast to this many views with
+    // VK_KHR_multiview (core in 1.1), shaders select per view data with gl_ViewIndex
+    // the render target and attachments get a layer per view, startup fails if the device
+    // can't render that many views
+    fn multiview_count(&self) -> u32 {
+        1
+    }
+
+    // maximum number of frames the cpu may queue ahead of the gpu, lower values reduce
+    // input latency at the cost of throughput
+    fn max_frame_latency(&self) -> u32 {
+        2
+    }
+
+    // allocate attachments that are never read after the render pass as TRANSIENT_ATTACHMENT
+    // with lazily allocated memory, saves bandwidth on tiled (mobile) gpus
+    fn use_transient_attachments(&self) -> bool {
+        false
+    }
+
+    // only used with combined depth/stencil formats like D24_UNORM_S8_UINT
+    fn stencil_clear_value(&self) -> u32 {
+        0
+    }
+
+    // makes out of bounds buffer accesses return defined values instead of crashing, useful
+    // for half finished shaders, also enables VK_EXT_robustness2 when available
+    fn enable_robust_buffer_access(&self) -> bool {
+        false
+    }
+
+    // devices lacking any of these are not selected, if no device has them startup fails
+    // instead of silently running without the feature
+    fn required_device_features(&self) -> PhysicalDeviceFeatures {
+        PhysicalDeviceFeatures::default()
+    }
+
+    // with VK_EXT_swapchain_maintenance1 the presentation engine scales the swapchain while
+    // the window is being resized and it's only recreated once the size settles,
+    // ignored when the surface doesn't support the requested scaling
+    fn present_scaling(&self) -> Option<PresentScaling> {
+        None
+    }
+
+    // frame loops `AppContext::fps` and the other frame time statistics cover
+    fn frame_stats_frame_count(&self) -> usize {
+        120
+    }
+
+    // appends fps and frame times to the main window's title, updated once per second
+    fn show_frame_stats_in_title(&self) -> bool {
+        false
+    }
+
+    // size of `AppContext::timestamp_pool` for manual measurements with
+    // `vk_utils::write_timestamp`, 0 creates no pool
+    fn timestamp_query_count(&self) -> u32 {
+        0
+    }
+
+    // creates a depth attachment with every swapchain that `begin_rendering` attaches,
+    // it has a layer per view with multiview
+    fn depth_format(&self) -> Option<Format> {
+        None
+    }
+
+    // above TYPE_1 `begin_rendering` renders into a multisampled image created with
+    // the swapchain and resolves it into the swapchain image, lowered to the highest count
+    // the device supports for the color and depth attachments
+    fn msaa_samples(&self) -> SampleCountFlags {
+        SampleCountFlags::TYPE_1
+    }
+
+    // renders through views of this format instead of the swapchain format, e.g. a UNORM
+    // view of a sRGB swapchain to write already encoded values, must be the sRGB/UNORM
+    // counterpart of the chosen swapchain format, needs VK_KHR_swapchain_mutable_format
+    fn swapchain_view_format(&self) -> Option<Format> {
+        None
+    }
+
+    // every candidate supports the api version, extensions and required features, the
+    // default prefers discrete gpus, match on `name` to pin a specific gpu
+    fn select_physical_device<'a>(
+        &self,
+        candidates: &'a [PhysicalDeviceInfo],
+    ) -> anyhow::Result<&'a PhysicalDeviceInfo> {
+        select_best_physical_device(candidates)
+    }
+
+    // called from the validation layer callback for every message, messages it rejects are
+    // not logged or counted in `Vk::validation_error_count`
+    fn validation_message_filter(&self) -> Option<DebugMessageFilter> {
+        None
+    }
+
+    // directories `AppContext::resolve_asset` searches in order
+    fn asset_search_paths(&self) -> Vec<PathBuf> {
+        AssetResolver::default_search_paths()
+    }
+
+    // the pipeline cache is loaded from this file on startup and written back when `run`
+    // returns, so pipelines compile faster from the second run on
+    fn pipeline_cache_path(&self) -> Option<PathBuf> {
+        None
+    }
+
     fn get_title(&mut self) -> anyhow::Result<String>;
 
+    // reported to the driver and graphics tools in VkApplicationInfo
+    fn get_application_name(&mut self) -> anyhow::Result<String> {
+        self.get_title()
+    }
+
+    // packed with `ash::vk::make_api_version`
+    fn get_application_version(&self) -> u32 {
+        ash::vk::make_api_version(0, 0, 1, 0)
+    }
+
+    // called once after the first swapchain creation and before the first frame, the
+    // swapchain is missing if the window starts minimized
     fn init(&mut self, ctx: &mut AppContext) -> anyhow::Result<()> {
         Ok(())
     }
 
-    fn event(&mut self, ctx: &mut AppContext, event: WindowEvent) -> anyhow::Result<()> {
+    // `window` is the id of the window the event came from, 0 for the main window
+    fn event(
+        &mut self,
+        ctx: &mut AppContext,
+        window: usize,
+        event: WindowEvent,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    // reads back every presented frame of the main window and passes it to
+    // `on_frame_presented`, stalls the cpu until each frame is rendered so it's opt-in
+    fn capture_presented_frames(&self) -> bool {
+        false
+    }
+
+    // `pixels` are tightly packed rows of `format`, R8G8B8A8_SRGB or R8G8B8A8_UNORM
+    // depending on the swapchain, only the first view is captured with multiview
+    // the frame is read from the swapchain image, which needs a surface supporting TRANSFER_SRC
+    fn on_frame_presented(
+        &mut self,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        format: Format,
+    ) -> anyhow::Result<()> {
         Ok(())
     }
 
-    fn frame(&mut self, ctx: &mut AppContext) -> anyhow::Result<()>;
+    // called once per loop for every window that isn't minimized, the swapchain methods
+    // of `ctx` act on `window` during the call
+    fn frame(&mut self, ctx: &mut AppContext, window: usize) -> anyhow::Result<()>;
+
+    // called after every swapchain (re)creation of `window`, including the first one,
+    // not called while the window is minimized since no swapchain exists then
+    fn on_swapchain_recreated(
+        &mut self,
+        ctx: &mut AppContext,
+        window: usize,
+        extent: Extent2D,
+        image_count: usize,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
 pub fn run(mut app: impl App) -> anyhow::Result<()> {
@@ -245,76 +2922,447 @@ pub fn run(mut app: impl App) -> anyhow::Result<()> {
         .create_window(1920, 1080, &app.get_title()?, WindowMode::Windowed)
         .context("failed to create main window")?;
     main_window.set_key_polling(true);
+    main_window.set_framebuffer_size_polling(true);
 
-    let vk = Vk::new(&main_window)?;
-    let main_surface = create_surface(vk.entry(), vk.instance(), &main_window)?;
+    let (vk, main_surface) = Vk::create(
+        Some((&main_window, &main_window)),
+        &app.additional_instance_extensions(),
+        &app.additional_instance_layers(),
+        &app.required_device_extensions(),
+        &app.get_application_name()?,
+        app.get_application_version(),
+        app.api_version(),
+        &app.required_device_features(),
+        app.enable_robust_buffer_access(),
+        app.validation_message_filter(),
+        &|candidates| app.select_physical_device(candidates),
+    )?;
+    let main_surface = main_surface.context("no surface was created for the main window")?;
+    let multiview_count = app.multiview_count().max(1);
+    if multiview_count > 1 && multiview_count > vk.max_multiview_view_count() {
+        bail!(
+            "{multiview_count} views requested, the device supports {}",
+            vk.max_multiview_view_count()
+        );
+    }
+    let msaa_samples = clamp_sample_count(&vk, app.msaa_samples(), app.depth_format());
+    if msaa_samples != app.msaa_samples() {
+        log::warn!(
+            "{:?} samples requested, using {msaa_samples:?}",
+            app.msaa_samples()
+        );
+    }
+    let pipeline_cache_path = app.pipeline_cache_path();
+    if let Some(path) = &pipeline_cache_path {
+        // a missing or unreadable cache only costs compile time
+        match std::fs::read(path) {
+            Ok(data) => vk.merge_pipeline_cache_data(&data)?,
+            Err(err) => log::info!("not loading pipeline cache {}: {err}", path.display()),
+        }
+    }
+    let frame_latency_fences = create_frame_latency_fences(&vk, app.max_frame_latency().max(1))?;
+    let draw_2d = Draw2d::new(frame_latency_fences.len());
     let mut ctx = AppContext {
         glfw,
-        main_window,
-        main_surface,
+        windows: vec![Some(WindowContext::new(main_window, events, main_surface))],
+        current_window: 0,
         vk,
-        swapchain: None,
+        render_scale: app.render_scale(),
+        multiview_count,
+        msaa_samples,
+        frame_latency_fences,
+        frame_idx: 0,
+        start_instant: Instant::now(),
+        last_frame_instant: Instant::now(),
+        delta_time: Duration::ZERO,
+        frame_stats: FrameStats::new(app.frame_stats_frame_count()),
+        frame_stats_title: app
+            .show_frame_stats_in_title()
+            .then(|| app.get_title())
+            .transpose()?,
+        last_title_update: Instant::now(),
+        draw_2d,
+        use_transient_attachments: app.use_transient_attachments(),
+        stencil_clear_value: app.stencil_clear_value(),
+        injected_events: vec![],
+        asset_resolver: AssetResolver::new(app.asset_search_paths()),
+        input_recorder: None,
+        input_replay: None,
+        gpu_timeline: None,
+        timestamp_pool: None,
+        capture_presented_frames: app.capture_presented_frames(),
+        captured_frame: None,
+        deferred_destroy: DeferredDestroyQueue::default(),
+        #[cfg(feature = "renderdoc")]
+        renderdoc: RenderDoc::load(),
+        #[cfg(feature = "renderdoc")]
+        renderdoc_capture_requested: false,
     };
+    ctx.gpu_timeline = GpuTimeline::new(&ctx.vk, ctx.frame_latency_fences.len())?;
+    let timestamp_query_count = app.timestamp_query_count();
+    if timestamp_query_count > 0 {
+        if timestamp_valid_bits(&ctx.vk) > 0 {
+            ctx.timestamp_pool = Some(create_timestamp_pool(&ctx.vk, timestamp_query_count)?);
+        } else {
+            log::warn!("graphics queue doesn't support timestamps, no timestamp pool is created");
+        }
+    }
+
+    ctx.recreate_swapchain(&mut app, 0)?;
+    ctx.current_window = 0;
+    app.init(&mut ctx)?;
+    // loading in `init` shouldn't show up as a long first frame
+    ctx.start_instant = Instant::now();
+    ctx.last_frame_instant = ctx.start_instant;
+
+    while !ctx.main_window().should_close() {
+        ctx.close_requested_windows()?;
+        if ctx.windows.iter().flatten().all(|e| e.paused) {
+            // nothing to render into, sleep until a window is restored
+            ctx.glfw.wait_events();
+            // the time spent paused would otherwise show up as one long frame
+            ctx.last_frame_instant = Instant::now();
+        } else {
+            let now = Instant::now();
+            ctx.delta_time = now - ctx.last_frame_instant;
+            ctx.last_frame_instant = now;
+            ctx.frame_stats.record(ctx.delta_time);
+            ctx.update_frame_stats_title();
+            ctx.begin_renderdoc_capture();
+            // windows created during a frame are rendered starting with the next loop
+            for id in 0..ctx.windows.len() {
+                if ctx
+                    .window_context(id)
+                    .is_some_and(|e| e.swapchain_out_of_date)
+                {
+                    ctx.recreate_swapchain(&mut app, id)?;
+                }
+                if ctx.window_context(id).is_none_or(|e| e.paused) {
+                    continue;
+                }
 
-    ctx.recreate_swapchain(&app)?;
+                ctx.current_window = id;
+                if let Err(err) = ctx
+                    .wait_for_frame_latency()
+                    .and_then(|_| app.frame(&mut ctx, id))
+                    .and_then(|_| match ctx.captured_frame.take() {
+                        Some(frame) => app.on_frame_presented(
+                            &frame.pixels,
+                            frame.extent.width,
+                            frame.extent.height,
+                            frame.format,
+                        ),
+                        None => Ok(()),
+                    })
+                {
+                    if is_device_lost(&err) {
+                        ctx.vk.report_device_fault();
+                    }
+                    return Err(err);
+                }
+                ctx.frame_idx += 1;
+            }
+            ctx.end_renderdoc_capture();
+            ctx.glfw.poll_events();
+        }
+        let mut frame_events = vec![];
+        for (id, window) in ctx.windows.iter().enumerate() {
+            let Some(window) = window else {
+                continue;
+            };
+            for (_, event) in glfw::flush_messages(&window.events) {
+                // recordings only cover the main window
+                if id == 0 {
+                    if ctx.input_replay.is_some() && is_recordable(&event) {
+                        continue;
+                    }
+                    if let Some(recorder) = &mut ctx.input_recorder {
+                        recorder.record(ctx.frame_idx, &event)?;
+                    }
+                }
+                frame_events.push((id, event));
+            }
+        }
+        if let Some(replay) = &mut ctx.input_replay {
+            frame_events.extend(replay.due_events(ctx.frame_idx).into_iter().map(|e| (0, e)));
+            if replay.is_finished() {
+                ctx.input_replay = None;
+            }
+        }
+        frame_events.extend(ctx.injected_events.drain(..).map(|e| (0, e)));
 
-    while !ctx.main_window.should_close() {
-        app.frame(&mut ctx)?;
-        ctx.glfw.poll_events();
-        for (_, event) in glfw::flush_messages(&events) {
+        let mut resized = vec![false; ctx.windows.len()];
+        for (id, event) in frame_events {
             if app.should_auto_close() {
                 if let WindowEvent::Key(Key::Escape, _, Action::Press, _) = event {
-                    ctx.main_window.set_should_close(true);
-                    break;
+                    ctx.window_context_mut(id).window.set_should_close(true);
+                    if id == 0 {
+                        break;
+                    }
+                    continue;
                 }
             }
 
-            if let WindowEvent::FramebufferSize(_, _) = event {
-                ctx.recreate_swapchain(&app)?;
+            if let WindowEvent::FramebufferSize(width, height) = event {
+                resized[id] = true;
+                // a zero sized window always needs the swapchain to go away
+                let window = ctx.window_context_mut(id);
+                if window.swapchain_scales_on_resize() && width > 0 && height > 0 {
+                    window.resize_pending = true;
+                } else {
+                    ctx.recreate_swapchain(&mut app, id)?;
+                }
                 continue;
             }
 
-            app.event(&mut ctx, event.clone())?;
+            ctx.current_window = id;
+            app.event(&mut ctx, id, event.clone())?;
+        }
+        for (id, resized) in resized.into_iter().enumerate() {
+            if !resized && ctx.window_context(id).is_some_and(|e| e.resize_pending) {
+                ctx.window_context_mut(id).resize_pending = false;
+                ctx.recreate_swapchain(&mut app, id)?;
+            }
         }
     }
 
+    ctx.stop_recording_input()?;
+    ctx.drain()?;
+    if let Some(path) = &pipeline_cache_path {
+        let data = serialize_pipeline_cache(&ctx.vk, ctx.vk.pipeline_cache())?;
+        std::fs::write(path, data)
+            .with_context(|| format!("failed to write pipeline cache {}", path.display()))?;
+    }
+
     Ok(())
 }
 
-fn get_required_device_extensions() -> Vec<CString> {
-    vec![
-        // required by MoltenVK
-        unsafe { CStr::from_ptr("VK_KHR_portability_subset\0".as_ptr().cast()) },
-        ash::extensions::khr::Swapchain::name(),
-        ash::extensions::khr::DynamicRendering::name(),
-    ]
-    .into_iter()
-    .map(|e| CString::from(e))
-    .collect()
+fn create_frame_latency_fences(vk: &Vk, count: u32) -> anyhow::Result<Vec<Fence>> {
+    let create_info = FenceCreateInfo::builder()
+        .flags(FenceCreateFlags::SIGNALED)
+        .build();
+    (0..count)
+        .map(|_| unsafe {
+            vk.device()
+                .create_fence(&create_info, None)
+                .context("failed to create frame latency fence")
+        })
+        .collect()
+}
+
+fn color_subresource_range() -> ImageSubresourceRange {
+    ImageSubresourceRange::builder()
+        .aspect_mask(ImageAspectFlags::COLOR)
+        .layer_count(1)
+        .level_count(1)
+        .build()
+}
+
+fn extent_to_offset(extent: Extent2D) -> Offset3D {
+    Offset3D {
+        x: extent.width as i32,
+        y: extent.height as i32,
+        z: 1,
+    }
+}
+
+fn is_device_lost(err: &anyhow::Error) -> bool {
+    err.chain().any(|e| {
+        e.is::<DeviceLost>()
+            || e.downcast_ref::<ash::vk::Result>() == Some(&ash::vk::Result::ERROR_DEVICE_LOST)
+    })
+}
+
+fn get_required_device_extensions(api_version: u32, swapchain: bool) -> Vec<CString> {
+    let extensions = swapchain
+        .then(ash::extensions::khr::Swapchain::name)
+        .into_iter();
+    // dynamic rendering is core in 1.3
+    let extensions = extensions.chain(
+        (api_version < ash::vk::API_VERSION_1_3).then(ash::extensions::khr::DynamicRendering::name),
+    );
+    // required by MoltenVK, desktop drivers on other platforms don't expose it
+    #[cfg(target_os = "macos")]
+    let extensions = extensions.chain([ash::vk::KhrPortabilitySubsetFn::name()]);
+
+    extensions.map(CString::from).collect()
+}
+
+// transitioned to `layout` right away, it never leaves it
+fn create_swapchain_attachment(
+    vk: &mut Vk,
+    desc: &SwapchainAttachmentsDesc,
+    format: Format,
+    usage: ImageUsageFlags,
+    aspect_mask: ImageAspectFlags,
+    layout: ImageLayout,
+) -> anyhow::Result<AttachmentImage> {
+    let attachment = AttachmentImage::new(
+        vk,
+        desc.extent,
+        format,
+        usage,
+        desc.samples,
+        aspect_mask,
+        desc.layers,
+        desc.transient,
+    )?;
+    let result = vk.immediate_submit(|cmd| {
+        image_barrier(
+            vk,
+            cmd,
+            attachment.image(),
+            ImageLayout::UNDEFINED,
+            layout,
+            aspect_mask,
+        )
+    });
+    if let Err(err) = result {
+        attachment.destroy(vk)?;
+        return Err(err);
+    }
+
+    Ok(attachment)
+}
+
+// swapchain attachments are sized to fit both the swapchain and the render target, they
+// are only attached when rendering at most that size
+fn attachment_fits(attachment: &AttachmentImage, extent: Extent2D) -> bool {
+    extent.width <= attachment.extent().width && extent.height <= attachment.extent().height
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create_swapchain(
-    vk: &Vk,
+    vk: &mut Vk,
     surface: &SurfaceKHR,
     image_format: Format,
+    view_format: Format,
     image_color_space: ColorSpaceKHR,
+    present_mode: PresentModeKHR,
     image_usage: ImageUsageFlags,
     image_extent: Extent2D,
     min_image_count: u32,
+    present_scaling: Option<PresentScaling>,
+    attachments: SwapchainAttachmentsDesc,
 ) -> anyhow::Result<SwapchainHolder> {
-    let create_info = SwapchainCreateInfoKHR::builder()
+    let mutable_format = view_format != image_format;
+    if mutable_format {
+        if !is_view_format_compatible(image_format, view_format) {
+            bail!("swapchain view format {view_format:?} is not compatible with {image_format:?}");
+        }
+        if !vk.supports_swapchain_mutable_format() {
+            bail!("swapchain view format {view_format:?} requires VK_KHR_swapchain_mutable_format");
+        }
+    }
+
+    let supported_present_modes = unsafe {
+        vk.khr_surface()
+            .get_physical_device_surface_present_modes(*vk.physical_device(), *surface)
+            .context("failed to query surface present modes")?
+    };
+    // FIFO is always supported
+    let present_mode = if supported_present_modes.contains(&present_mode) {
+        present_mode
+    } else {
+        log::warn!("present mode {present_mode:?} is not supported, falling back to FIFO");
+        PresentModeKHR::FIFO
+    };
+
+    let surface_capabilities = vk.query_surface_capabilities(*surface, present_mode)?;
+    let capabilities = surface_capabilities.capabilities;
+    // a max_image_count of 0 means there is no upper limit
+    let max_image_count = if capabilities.max_image_count == 0 {
+        u32::MAX
+    } else {
+        capabilities.max_image_count
+    };
+    let clamped_image_count = min_image_count.clamp(capabilities.min_image_count, max_image_count);
+    if clamped_image_count != min_image_count {
+        log::warn!(
+            "requested {min_image_count} swapchain images, surface supports [{}, {}], using {clamped_image_count}",
+            capabilities.min_image_count,
+            capabilities.max_image_count
+        );
+    }
+
+    // transfers are only needed for readback and the render target blit, COLOR_ATTACHMENT
+    // is always supported
+    let optional_usage = ImageUsageFlags::TRANSFER_SRC | ImageUsageFlags::TRANSFER_DST;
+    let image_usage = image_usage & (capabilities.supported_usage_flags | !optional_usage);
+
+    // not every surface supports IDENTITY or OPAQUE (e.g. rotated mobile displays)
+    let pre_transform = capabilities.current_transform;
+    let composite_alpha = [
+        CompositeAlphaFlagsKHR::OPAQUE,
+        CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+        CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+        CompositeAlphaFlagsKHR::INHERIT,
+    ]
+    .into_iter()
+    .find(|e| capabilities.supported_composite_alpha.contains(*e))
+    .context("surface does not support any composite alpha mode")?;
+
+    // images are written by the graphics queue and read by the present queue,
+    // share them instead of doing ownership transfers when those differ
+    let queue_family_indices = [
+        vk.queue_family_indices.graphics,
+        vk.queue_family_indices.present,
+    ];
+    let image_sharing_mode = if vk.queue_family_indices.is_separate_present() {
+        SharingMode::CONCURRENT
+    } else {
+        SharingMode::EXCLUSIVE
+    };
+
+    // only used when the surface supports the exact behavior requested, otherwise the
+    // swapchain is recreated on resize as usual
+    let present_scaling = present_scaling.filter(|scaling| {
+        vk.supports_swapchain_maintenance1()
+            && surface_capabilities
+                .present_scaling
+                .is_some_and(|supported| {
+                    supported.supported_scaling.contains(scaling.scaling)
+                        && supported.supported_gravity_x.contains(scaling.gravity_x)
+                        && supported.supported_gravity_y.contains(scaling.gravity_y)
+                })
+    });
+    let mut present_scaling_info = present_scaling.map_or_else(Default::default, |scaling| {
+        SwapchainPresentScalingCreateInfoEXT::builder()
+            .scaling_behavior(scaling.scaling)
+            .present_gravity_x(scaling.gravity_x)
+            .present_gravity_y(scaling.gravity_y)
+            .build()
+    });
+
+    // both formats have to be listed with MUTABLE_FORMAT
+    let view_formats = [image_format, view_format];
+    let mut format_list_info = ImageFormatListCreateInfo::builder()
+        .view_formats(&view_formats)
+        .build();
+
+    let mut create_info = SwapchainCreateInfoKHR::builder()
         .surface(surface.clone())
+        .image_sharing_mode(image_sharing_mode)
+        .queue_family_indices(&queue_family_indices)
         .image_format(image_format)
         .image_usage(image_usage)
         .image_extent(image_extent)
-        .present_mode(PresentModeKHR::FIFO)
-        .pre_transform(SurfaceTransformFlagsKHR::IDENTITY)
+        .present_mode(present_mode)
+        .pre_transform(pre_transform)
         .image_array_layers(1)
-        .min_image_count(min_image_count)
+        .min_image_count(clamped_image_count)
         .clipped(true)
-        .composite_alpha(CompositeAlphaFlagsKHR::OPAQUE)
-        .image_color_space(image_color_space)
-        .build();
+        .composite_alpha(composite_alpha)
+        .image_color_space(image_color_space);
+    if present_scaling.is_some() {
+        create_info = create_info.push_next(&mut present_scaling_info);
+    }
+    if mutable_format {
+        create_info = create_info
+            .flags(SwapchainCreateFlagsKHR::MUTABLE_FORMAT)
+            .push_next(&mut format_list_info);
+    }
+    let create_info = create_info.build();
 
     let swapchain = unsafe {
         vk.khr_swapchain()
@@ -322,23 +3370,20 @@ fn create_swapchain(
             .context("failed to create swapchain")?
     };
 
+    set_object_name(vk, swapchain, "swapchain");
+
     let images = unsafe { vk.khr_swapchain().get_swapchain_images(swapchain)? };
 
     let mut image_views = vec![];
 
-    for image in &images {
+    for (idx, image) in images.iter().enumerate() {
+        set_object_name(vk, *image, &format!("swapchain image {idx}"));
         let create_info = ImageViewCreateInfo::builder()
-            .format(image_format)
+            .format(view_format)
             .view_type(ImageViewType::TYPE_2D)
             .image(image.clone())
             .components(ComponentMapping::builder().build())
-            .subresource_range(
-                ImageSubresourceRange::builder()
-                    .aspect_mask(ImageAspectFlags::COLOR)
-                    .layer_count(1)
-                    .level_count(1)
-                    .build(),
-            )
+            .subresource_range(color_subresource_range())
             .build();
 
         let image_view = unsafe {
@@ -346,12 +3391,157 @@ fn create_swapchain(
                 .create_image_view(&create_info, None)
                 .context("failed to create image view")?
         };
+        set_object_name(vk, image_view, &format!("swapchain image view {idx}"));
 
         image_views.push(image_view);
     }
-    Ok(SwapchainHolder {
+    let depth = attachments
+        .depth_format
+        .map(|format| {
+            let format_properties = unsafe {
+                vk.instance()
+                    .get_physical_device_format_properties(*vk.physical_device(), format)
+            };
+            if !format_properties
+                .optimal_tiling_features
+                .contains(FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+            {
+                bail!("{format:?} can't be used as a depth attachment");
+            }
+            create_swapchain_attachment(
+                vk,
+                &attachments,
+                format,
+                ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                depth_stencil_aspect_mask(format),
+                DEPTH_STENCIL_ATTACHMENT_LAYOUT,
+            )
+        })
+        .transpose()?;
+    let msaa_color = (attachments.samples != SampleCountFlags::TYPE_1)
+        .then(|| {
+            create_swapchain_attachment(
+                vk,
+                &attachments,
+                view_format,
+                ImageUsageFlags::COLOR_ATTACHMENT,
+                ImageAspectFlags::COLOR,
+                ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            )
+        })
+        .transpose()?;
+    let holder = SwapchainHolder {
         swapchain,
+        format: image_format,
+        view_format,
+        color_space: image_color_space,
+        extent: image_extent,
+        pre_transform,
+        composite_alpha,
+        scales_on_resize: present_scaling.is_some(),
+        image_usage,
         images,
         image_views,
-    })
+        depth,
+        msaa_color,
+    };
+    log::debug!(
+        "created swapchain {:?} {}x{} with {} images, transform {:?}, composite alpha {:?}",
+        holder.format,
+        holder.extent.width,
+        holder.extent.height,
+        holder.images.len(),
+        holder.pre_transform,
+        holder.composite_alpha
+    );
+
+    Ok(holder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // tests using it are ignored by default, run them with `cargo test -- --ignored`
+    fn headless_vk() -> Vk {
+        let extent = Extent2D {
+            width: 4,
+            height: 4,
+        };
+        VkHeadlessBuilder::new(extent, Format::R8G8B8A8_UNORM)
+            .build()
+            .expect("a usable vulkan device")
+    }
+
+    #[test]
+    #[ignore = "needs a vulkan device"]
+    fn upload_to_buffer_round_trips_vertices() {
+        #[derive(Clone, Copy)]
+        #[repr(C)]
+        struct Vertex {
+            position: [f32; 2],
+            color: [f32; 3],
+        }
+
+        let vk = headless_vk();
+        let vertices = [
+            Vertex {
+                position: [0.0, -0.5],
+                color: [1.0, 0.0, 0.0],
+            },
+            Vertex {
+                position: [0.5, 0.5],
+                color: [0.0, 1.0, 0.0],
+            },
+            Vertex {
+                position: [-0.5, 0.5],
+                color: [0.0, 0.0, 1.0],
+            },
+        ];
+        let size = std::mem::size_of_val(&vertices);
+        let bytes = unsafe { std::slice::from_raw_parts(vertices.as_ptr().cast::<u8>(), size) };
+
+        // device local, so the upload goes through a staging buffer
+        let buffer = vk
+            .create_buffer(
+                size as u64,
+                BufferUsageFlags::VERTEX_BUFFER | BufferUsageFlags::TRANSFER_SRC,
+                MemoryLocation::GpuOnly,
+            )
+            .unwrap();
+        vk.upload_to_buffer(&buffer, bytes).unwrap();
+
+        let readback = vk
+            .create_buffer(
+                size as u64,
+                BufferUsageFlags::TRANSFER_DST,
+                MemoryLocation::GpuToCpu,
+            )
+            .unwrap();
+        vk.immediate_submit(|cmd| unsafe {
+            let region = BufferCopy::builder().size(size as u64).build();
+            vk.device()
+                .cmd_copy_buffer(cmd, buffer.buffer(), readback.buffer(), &[region]);
+            let barrier = ash::vk::MemoryBarrier::builder()
+                .src_access_mask(AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(AccessFlags::HOST_READ)
+                .build();
+            vk.device().cmd_pipeline_barrier(
+                cmd,
+                PipelineStageFlags::TRANSFER,
+                PipelineStageFlags::HOST,
+                DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+        })
+        .unwrap();
+
+        let mapped = readback
+            .allocation()
+            .mapped_slice()
+            .expect("readback memory is host visible");
+        assert_eq!(&mapped[..size], bytes);
+    }
 }
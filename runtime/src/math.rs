@@ -0,0 +1,36 @@
+// column major like GLSL, `m[column][row]`, can be copied into uniform and push constant
+// blocks as is
+pub type Mat4 = [[f32; 4]; 4];
+
+pub const IDENTITY: Mat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+// right handed view space looking down -z, `near` and `far` are distances along -z
+// `top` ends up at the top of the screen (vulkan's clip space y points down) and depth is
+// mapped to 0..1 from `near` to `far`
+pub fn ortho(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Mat4 {
+    let width = right - left;
+    let height = bottom - top;
+    let depth = far - near;
+    [
+        [2.0 / width, 0.0, 0.0, 0.0],
+        [0.0, 2.0 / height, 0.0, 0.0],
+        [0.0, 0.0, -1.0 / depth, 0.0],
+        [
+            -(right + left) / width,
+            -(bottom + top) / height,
+            -near / depth,
+            1.0,
+        ],
+    ]
+}
+
+// pixel coordinates with the origin in the top left corner and y pointing down,
+// z = 0 maps to depth 0 and z = -1 to depth 1
+pub fn ortho_2d(width: f32, height: f32) -> Mat4 {
+    ortho(0.0, width, height, 0.0, 0.0, 1.0)
+}
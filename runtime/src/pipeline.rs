@@ -0,0 +1,350 @@
+use std::ffi::{CStr, CString};
+
+use anyhow::{bail, Context};
+use ash::vk;
+use ash::vk::Format;
+
+use crate::vk_utils::{create_pipeline_layout, format_has_stencil};
+use crate::Vk;
+
+// assembles a graphics pipeline meant for VK_KHR_dynamic_rendering, no render pass needed
+// viewport and scissor are dynamic by default
+pub struct GraphicsPipelineBuilder {
+    vertex_shader: Option<(vk::ShaderModule, CString)>,
+    fragment_shader: Option<(vk::ShaderModule, CString)>,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    topology: vk::PrimitiveTopology,
+    primitive_restart: bool,
+    rasterization: vk::PipelineRasterizationStateCreateInfo,
+    multisample: vk::PipelineMultisampleStateCreateInfo,
+    depth_stencil: vk::PipelineDepthStencilStateCreateInfo,
+    color_blend_attachments: Vec<vk::PipelineColorBlendAttachmentState>,
+    dynamic_states: Vec<vk::DynamicState>,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    color_formats: Vec<Format>,
+    depth_format: Format,
+    view_mask: u32,
+}
+
+impl Default for GraphicsPipelineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GraphicsPipelineBuilder {
+    pub fn new() -> Self {
+        Self {
+            vertex_shader: None,
+            fragment_shader: None,
+            vertex_bindings: vec![],
+            vertex_attributes: vec![],
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart: false,
+            rasterization: vk::PipelineRasterizationStateCreateInfo::builder()
+                .polygon_mode(vk::PolygonMode::FILL)
+                .cull_mode(vk::CullModeFlags::NONE)
+                .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                .line_width(1.0)
+                .build(),
+            multisample: vk::PipelineMultisampleStateCreateInfo::builder()
+                .rasterization_samples(vk::SampleCountFlags::TYPE_1)
+                .build(),
+            depth_stencil: vk::PipelineDepthStencilStateCreateInfo::default(),
+            color_blend_attachments: vec![],
+            dynamic_states: vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR],
+            set_layouts: vec![],
+            push_constant_ranges: vec![],
+            color_formats: vec![],
+            depth_format: Format::UNDEFINED,
+            view_mask: 0,
+        }
+    }
+
+    pub fn vertex_shader(mut self, module: vk::ShaderModule, entry: &CStr) -> Self {
+        self.vertex_shader = Some((module, CString::from(entry)));
+        self
+    }
+
+    pub fn fragment_shader(mut self, module: vk::ShaderModule, entry: &CStr) -> Self {
+        self.fragment_shader = Some((module, CString::from(entry)));
+        self
+    }
+
+    pub fn vertex_input(
+        mut self,
+        bindings: &[vk::VertexInputBindingDescription],
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> Self {
+        self.vertex_bindings = bindings.to_vec();
+        self.vertex_attributes = attributes.to_vec();
+        self
+    }
+
+    pub fn input_assembly(
+        mut self,
+        topology: vk::PrimitiveTopology,
+        primitive_restart: bool,
+    ) -> Self {
+        self.topology = topology;
+        self.primitive_restart = primitive_restart;
+        self
+    }
+
+    pub fn rasterization(
+        mut self,
+        rasterization: vk::PipelineRasterizationStateCreateInfo,
+    ) -> Self {
+        self.rasterization = rasterization;
+        self
+    }
+
+    pub fn multisample(mut self, multisample: vk::PipelineMultisampleStateCreateInfo) -> Self {
+        self.multisample = multisample;
+        self
+    }
+
+    // shorthand for `multisample` when only the sample count differs, must match the
+    // attachments, see `AppContext::msaa_samples`
+    pub fn rasterization_samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.multisample.rasterization_samples = samples;
+        self
+    }
+
+    pub fn depth_stencil(mut self, depth_stencil: vk::PipelineDepthStencilStateCreateInfo) -> Self {
+        self.depth_stencil = depth_stencil;
+        self
+    }
+
+    // one state per color attachment, defaults to no blending with full write mask
+    pub fn color_blend(mut self, attachments: &[vk::PipelineColorBlendAttachmentState]) -> Self {
+        self.color_blend_attachments = attachments.to_vec();
+        self
+    }
+
+    pub fn dynamic_states(mut self, dynamic_states: &[vk::DynamicState]) -> Self {
+        self.dynamic_states = dynamic_states.to_vec();
+        self
+    }
+
+    // appended to the ranges, `layout` appends its ranges as well so both can be combined
+    pub fn push_constant_range(
+        mut self,
+        stages: vk::ShaderStageFlags,
+        offset: u32,
+        size: u32,
+    ) -> Self {
+        self.push_constant_ranges.push(vk::PushConstantRange {
+            stage_flags: stages,
+            offset,
+            size,
+        });
+        self
+    }
+
+    pub fn layout(
+        mut self,
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_constant_ranges: &[vk::PushConstantRange],
+    ) -> Self {
+        self.set_layouts = set_layouts.to_vec();
+        self.push_constant_ranges
+            .extend_from_slice(push_constant_ranges);
+        self
+    }
+
+    // attachment formats used with vkCmdBeginRendering, pass Format::UNDEFINED for no depth
+    pub fn render_format(mut self, color_formats: &[Format], depth_format: Format) -> Self {
+        self.color_formats = color_formats.to_vec();
+        self.depth_format = depth_format;
+        self
+    }
+
+    // with multiview every draw is broadcast to the views set in the mask, it must match
+    // the mask rendering is begun with
+    pub fn view_mask(mut self, view_mask: u32) -> Self {
+        self.view_mask = view_mask;
+        self
+    }
+
+    pub fn build(&self, vk: &Vk) -> anyhow::Result<(vk::Pipeline, vk::PipelineLayout)> {
+        let mut pipelines = Self::build_many(vk, std::slice::from_ref(self))?;
+        Ok(pipelines.remove(0))
+    }
+
+    // creates every pipeline with a single vkCreateGraphicsPipelines call so the driver can
+    // compile them together, nothing is leaked if any of them fails
+    pub fn build_many(
+        vk: &Vk,
+        builders: &[GraphicsPipelineBuilder],
+    ) -> anyhow::Result<Vec<(vk::Pipeline, vk::PipelineLayout)>> {
+        let device = vk.device();
+        let destroy_layouts = |prepared: &[PreparedGraphicsPipeline]| {
+            for e in prepared {
+                vk.destroy_pipeline_layout(e.layout);
+            }
+        };
+
+        let mut prepared = Vec::with_capacity(builders.len());
+        for builder in builders {
+            match builder.prepare(vk) {
+                Ok(e) => prepared.push(e),
+                Err(err) => {
+                    destroy_layouts(&prepared);
+                    return Err(err);
+                }
+            }
+        }
+
+        // the create infos point into `prepared`, it must not be modified until the call is done
+        let create_infos: Vec<_> = builders
+            .iter()
+            .zip(prepared.iter_mut())
+            .map(|(builder, prepared)| builder.create_info(prepared))
+            .collect();
+        let pipelines =
+            unsafe { device.create_graphics_pipelines(vk.pipeline_cache(), &create_infos, None) };
+
+        match pipelines {
+            Ok(pipelines) => Ok(pipelines
+                .into_iter()
+                .zip(prepared.iter().map(|e| e.layout))
+                .collect()),
+            Err((pipelines, err)) => {
+                // failed pipelines are returned as null handles
+                let mut failed = vec![];
+                for (idx, pipeline) in pipelines.into_iter().enumerate() {
+                    if pipeline == vk::Pipeline::null() {
+                        failed.push(idx);
+                    } else {
+                        unsafe { device.destroy_pipeline(pipeline, None) };
+                    }
+                }
+                destroy_layouts(&prepared);
+                Err(err).with_context(|| format!("failed to create graphics pipelines {failed:?}"))
+            }
+        }
+    }
+
+    fn prepare(&self, vk: &Vk) -> anyhow::Result<PreparedGraphicsPipeline> {
+        let mut stages = vec![];
+        if let Some((module, entry)) = &self.vertex_shader {
+            stages.push(
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(vk::ShaderStageFlags::VERTEX)
+                    .module(*module)
+                    .name(entry)
+                    .build(),
+            );
+        }
+        if let Some((module, entry)) = &self.fragment_shader {
+            stages.push(
+                vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(vk::ShaderStageFlags::FRAGMENT)
+                    .module(*module)
+                    .name(entry)
+                    .build(),
+            );
+        }
+        if stages.is_empty() {
+            bail!("graphics pipeline requires at least a vertex shader");
+        }
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::builder()
+            .vertex_binding_descriptions(&self.vertex_bindings)
+            .vertex_attribute_descriptions(&self.vertex_attributes)
+            .build();
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
+            .topology(self.topology)
+            .primitive_restart_enable(self.primitive_restart)
+            .build();
+        let viewport = vk::PipelineViewportStateCreateInfo::builder()
+            .viewport_count(1)
+            .scissor_count(1)
+            .build();
+
+        let default_color_blend_attachments: Vec<_> = self
+            .color_formats
+            .iter()
+            .map(|_| {
+                vk::PipelineColorBlendAttachmentState::builder()
+                    .color_write_mask(vk::ColorComponentFlags::RGBA)
+                    .build()
+            })
+            .collect();
+        // points into the heap allocation, which stays put when the vec is moved
+        let color_blend_attachments = if self.color_blend_attachments.is_empty() {
+            &default_color_blend_attachments
+        } else {
+            &self.color_blend_attachments
+        };
+        let color_blend = vk::PipelineColorBlendStateCreateInfo::builder()
+            .attachments(color_blend_attachments)
+            .build();
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::builder()
+            .dynamic_states(&self.dynamic_states)
+            .build();
+
+        // combined depth/stencil formats are used for both attachments
+        let stencil_format = if format_has_stencil(self.depth_format) {
+            self.depth_format
+        } else {
+            Format::UNDEFINED
+        };
+        let rendering = vk::PipelineRenderingCreateInfoKHR::builder()
+            .color_attachment_formats(&self.color_formats)
+            .depth_attachment_format(self.depth_format)
+            .stencil_attachment_format(stencil_format)
+            .view_mask(self.view_mask)
+            .build();
+
+        let layout = create_pipeline_layout(vk, &self.set_layouts, &self.push_constant_ranges)?;
+
+        Ok(PreparedGraphicsPipeline {
+            stages,
+            vertex_input,
+            input_assembly,
+            viewport,
+            _default_color_blend_attachments: default_color_blend_attachments,
+            color_blend,
+            dynamic_state,
+            rendering,
+            layout,
+        })
+    }
+
+    fn create_info(
+        &self,
+        prepared: &mut PreparedGraphicsPipeline,
+    ) -> vk::GraphicsPipelineCreateInfo {
+        vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&prepared.stages)
+            .vertex_input_state(&prepared.vertex_input)
+            .input_assembly_state(&prepared.input_assembly)
+            .viewport_state(&prepared.viewport)
+            .rasterization_state(&self.rasterization)
+            .multisample_state(&self.multisample)
+            .depth_stencil_state(&self.depth_stencil)
+            .color_blend_state(&prepared.color_blend)
+            .dynamic_state(&prepared.dynamic_state)
+            .layout(prepared.layout)
+            .push_next(&mut prepared.rendering)
+            .build()
+    }
+}
+
+// state a GraphicsPipelineCreateInfo points to besides the builder itself
+struct PreparedGraphicsPipeline {
+    stages: Vec<vk::PipelineShaderStageCreateInfo>,
+    vertex_input: vk::PipelineVertexInputStateCreateInfo,
+    input_assembly: vk::PipelineInputAssemblyStateCreateInfo,
+    viewport: vk::PipelineViewportStateCreateInfo,
+    // referenced by `color_blend` when the builder has no blend attachments
+    _default_color_blend_attachments: Vec<vk::PipelineColorBlendAttachmentState>,
+    color_blend: vk::PipelineColorBlendStateCreateInfo,
+    dynamic_state: vk::PipelineDynamicStateCreateInfo,
+    rendering: vk::PipelineRenderingCreateInfoKHR,
+    layout: vk::PipelineLayout,
+}
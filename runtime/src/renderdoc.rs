@@ -0,0 +1,100 @@
+use std::ffi::{c_int, c_void};
+
+use anyhow::Context;
+use libloading::Library;
+
+// eRENDERDOC_API_Version_1_1_2, the function table below matches it
+const API_VERSION_1_1_2: c_int = 10102;
+
+type GetApiFn = unsafe extern "C" fn(version: c_int, api: *mut *mut c_void) -> c_int;
+
+// RENDERDOC_API_1_1_2 from renderdoc_app.h
+#[repr(C)]
+struct RenderDocApi {
+    // GetAPIVersion up to SetActiveWindow
+    _unused: [*const c_void; 19],
+    start_frame_capture: unsafe extern "C" fn(device: *mut c_void, window: *mut c_void),
+    is_frame_capturing: unsafe extern "C" fn() -> u32,
+    end_frame_capture: unsafe extern "C" fn(device: *mut c_void, window: *mut c_void) -> u32,
+}
+
+// RenderDoc's in-application API, only available when the process was started from
+// RenderDoc (or it was injected), loading the library ourselves would come too late to
+// hook the vulkan loader
+pub(crate) struct RenderDoc {
+    api: *const RenderDocApi,
+    // keeps `api` valid
+    _library: Library,
+}
+
+impl RenderDoc {
+    // None when RenderDoc isn't attached
+    pub fn load() -> Option<Self> {
+        match Self::load_attached() {
+            Ok(renderdoc) => renderdoc,
+            Err(err) => {
+                log::warn!("failed to load the RenderDoc API: {err:#}");
+                None
+            }
+        }
+    }
+
+    fn load_attached() -> anyhow::Result<Option<Self>> {
+        let Some(library) = open_attached_library() else {
+            return Ok(None);
+        };
+        let mut api = std::ptr::null_mut();
+        let result = unsafe {
+            let get_api = library
+                .get::<GetApiFn>(b"RENDERDOC_GetAPI\0")
+                .context("failed to find RENDERDOC_GetAPI")?;
+            get_api(API_VERSION_1_1_2, &mut api)
+        };
+        if result != 1 || api.is_null() {
+            anyhow::bail!("RenderDoc doesn't support API version 1.1.2");
+        }
+        log::info!("RenderDoc is attached, frame captures can be triggered");
+
+        Ok(Some(Self {
+            api: api as *const RenderDocApi,
+            _library: library,
+        }))
+    }
+
+    // null device and window capture the only device and window there is
+    pub fn start_frame_capture(&self) {
+        unsafe { ((*self.api).start_frame_capture)(std::ptr::null_mut(), std::ptr::null_mut()) }
+    }
+
+    pub fn is_frame_capturing(&self) -> bool {
+        unsafe { ((*self.api).is_frame_capturing)() != 0 }
+    }
+
+    pub fn end_frame_capture(&self) -> bool {
+        unsafe { ((*self.api).end_frame_capture)(std::ptr::null_mut(), std::ptr::null_mut()) != 0 }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_attached_library() -> Option<Library> {
+    use libloading::os::unix::{Library, RTLD_NOW};
+    // not exported by libloading, the value differs between unix platforms
+    const RTLD_NOLOAD: c_int = 0x4;
+
+    unsafe { Library::open(Some("librenderdoc.so"), RTLD_NOW | RTLD_NOLOAD) }
+        .ok()
+        .map(Into::into)
+}
+
+#[cfg(windows)]
+fn open_attached_library() -> Option<Library> {
+    libloading::os::windows::Library::open_already_loaded("renderdoc.dll")
+        .ok()
+        .map(Into::into)
+}
+
+// RenderDoc only supports Linux and Windows
+#[cfg(not(any(target_os = "linux", windows)))]
+fn open_attached_library() -> Option<Library> {
+    None
+}
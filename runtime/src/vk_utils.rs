@@ -1,59 +1,137 @@
-use std::collections::{BTreeMap, HashSet};
-use std::ffi::{c_char, CStr, CString};
+use std::collections::HashSet;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
 
 use anyhow::{bail, Context};
-use ash::{Device, Entry, Instance, vk};
-use ash::vk::{API_VERSION_1_2, ApplicationInfo, InstanceCreateInfo, SurfaceKHR};
+use ash::extensions::ext::DebugUtils;
+use ash::vk::{
+    ApplicationInfo, Bool32, ColorSpaceKHR, DebugUtilsMessageSeverityFlagsEXT,
+    DebugUtilsMessageTypeFlagsEXT, DebugUtilsMessengerCallbackDataEXT,
+    DebugUtilsMessengerCreateInfoEXT, DebugUtilsMessengerEXT, Format, InstanceCreateInfo,
+    SurfaceFormatKHR, SurfaceKHR, API_VERSION_1_1, API_VERSION_1_2,
+};
+use ash::{vk, Device, Entry, Instance};
 use ash_window::enumerate_required_extensions;
+use gpu_allocator::vulkan::{Allocation, Allocator};
+use gpu_allocator::MemoryLocation;
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
-use vk::{DeviceCreateInfo, DeviceQueueCreateInfo, PhysicalDevice, PhysicalDeviceDynamicRenderingFeaturesKHR, PhysicalDeviceFeatures, PhysicalDeviceType};
+
+use crate::Vk;
+
+use vk::{
+    DeviceCreateInfo, DeviceFaultAddressInfoEXT, DeviceFaultCountsEXT, DeviceFaultInfoEXT,
+    DeviceFaultVendorInfoEXT, DeviceQueueCreateInfo, ExtDeviceFaultFn, PhysicalDevice,
+    PhysicalDeviceDynamicRenderingFeaturesKHR, PhysicalDeviceFaultFeaturesEXT,
+    PhysicalDeviceFeatures, PhysicalDeviceFeatures2, PhysicalDeviceType,
+};
 
 pub fn create_entry() -> anyhow::Result<Entry> {
     Ok(Entry::linked())
 }
 
-pub fn create_instance(entry: &Entry, display_handle: &dyn HasRawDisplayHandle) -> anyhow::Result<Instance> {
-    let mut required_extensions: Vec<_> = enumerate_required_extensions(display_handle.raw_display_handle())?
-        .iter()
-        .map(|e| unsafe { CString::from(CStr::from_ptr(*e)) })
-        .collect();
+pub fn create_instance(
+    entry: &Entry,
+    // None for headless instances, no surface extensions are enabled then
+    display_handle: Option<&dyn HasRawDisplayHandle>,
+    additional_extensions: &[CString],
+    additional_layers: &[CString],
+    application_name: &str,
+    application_version: u32,
+    api_version: u32,
+) -> anyhow::Result<Instance> {
+    // physical device features2/properties2 queries need at least 1.1
+    if api_version < API_VERSION_1_1 {
+        bail!(
+            "vulkan {} requested, at least 1.1 is required",
+            format_api_version(api_version)
+        );
+    }
+    let loader_version = entry
+        .try_enumerate_instance_version()
+        .context("failed to query instance version")?
+        .unwrap_or(vk::API_VERSION_1_0);
+    if api_version > loader_version {
+        bail!(
+            "vulkan {} requested but the loader only supports {}",
+            format_api_version(api_version),
+            format_api_version(loader_version)
+        );
+    }
+
+    // shows up in tools like RenderDoc and in driver logs
+    let application_name = CString::new(application_name.replace('\0', "")).unwrap();
+
+    let mut required_extensions: Vec<_> = match display_handle {
+        Some(display_handle) => enumerate_required_extensions(display_handle.raw_display_handle())?
+            .iter()
+            .map(|e| unsafe { CString::from(CStr::from_ptr(*e)) })
+            .collect(),
+        None => vec![],
+    };
 
     let mut instance_create_flags = vk::InstanceCreateFlags::empty();
     // required by MoltenVK
     #[cfg(target_os = "macos")]
     {
-        required_extensions
-            .push(CString::new("VK_KHR_portability_enumeration").unwrap());
+        required_extensions.push(CString::new("VK_KHR_portability_enumeration").unwrap());
         instance_create_flags |= vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR;
     }
 
+    if cfg!(feature = "validation_layers") {
+        required_extensions.push(CString::from(DebugUtils::name()));
+    }
+
+    if !additional_extensions.is_empty() {
+        let available_extensions = enumerate_instance_extensions(entry)?;
+
+        for extension in additional_extensions {
+            if required_extensions.contains(extension) {
+                continue;
+            }
+
+            if available_extensions.contains(extension) {
+                required_extensions.push(extension.clone());
+            } else {
+                log::warn!("instance extension {extension:?} is not available, skipping");
+            }
+        }
+    }
+
     let required_extensions_ptr: Vec<_> =
         required_extensions.iter().map(|arg| arg.as_ptr()).collect();
 
-    let layers = if cfg!(feature = "validation_layers") {
-        let required_layers = HashSet::from(["VK_LAYER_KHRONOS_validation"]);
-
-        let res: Vec<_> = entry
-            .enumerate_instance_layer_properties()
-            .expect(
-                "validation layers should be available when `validation_layers` feature is enabled.",
-            )
-            .iter()
-            .map(|layer_info| layer_info.layer_name)
-            .filter(|e| unsafe {
-                let c_str = CStr::from_ptr(e.as_ptr());
-                required_layers.contains(c_str.to_str().unwrap())
-            })
-            .collect();
+    let available_layers: HashSet<CString> = entry
+        .enumerate_instance_layer_properties()
+        .context("failed to enumerate instance layers")?
+        .iter()
+        .map(|e| unsafe { CString::from(CStr::from_ptr(e.layer_name.as_ptr())) })
+        .collect();
 
-        if required_layers.len() != res.len() {
-            panic!("required layers not found");
+    let mut layers = vec![];
+    if cfg!(feature = "validation_layers") {
+        let validation_layer = CString::new("VK_LAYER_KHRONOS_validation").unwrap();
+        if !available_layers.contains(&validation_layer) {
+            bail!(
+                "validation layer {validation_layer:?} not found, install the Vulkan SDK \
+                or disable the `validation_layers` feature"
+            );
+        }
+        layers.push(validation_layer);
+    }
+    for layer in additional_layers {
+        if layers.contains(layer) {
+            continue;
         }
 
-        res
-    } else {
-        vec![]
-    };
+        if available_layers.contains(layer) {
+            layers.push(layer.clone());
+        } else {
+            log::warn!("instance layer {layer:?} is not available, skipping");
+        }
+    }
 
     let layers_ptr = layers
         .iter()
@@ -64,139 +142,3207 @@ pub fn create_instance(entry: &Entry, display_handle: &dyn HasRawDisplayHandle)
         .enabled_extension_names(required_extensions_ptr.as_slice())
         .enabled_layer_names(layers_ptr.as_slice())
         .flags(instance_create_flags)
-        .application_info(&ApplicationInfo::builder().api_version(API_VERSION_1_2).build())
+        .application_info(
+            &ApplicationInfo::builder()
+                .application_name(&application_name)
+                .application_version(application_version)
+                .api_version(api_version)
+                .build(),
+        )
+        .build();
+
+    unsafe {
+        entry
+            .create_instance(&create_info, None)
+            .context("failed to create instance")
+    }
+}
+
+// return false to drop the message, dropped messages are neither logged nor counted
+pub type DebugMessageFilter = Box<
+    dyn Fn(DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT, &str) -> bool
+        + Send
+        + Sync,
+>;
+
+// shared with the debug messenger callback through its user data pointer,
+// must outlive the messenger
+#[derive(Default)]
+pub struct DebugMessengerState {
+    filter: Option<DebugMessageFilter>,
+    error_count: AtomicU64,
+    warning_count: AtomicU64,
+}
+
+impl DebugMessengerState {
+    pub fn new(filter: Option<DebugMessageFilter>) -> Self {
+        Self {
+            filter,
+            ..Default::default()
+        }
+    }
+
+    pub fn error_count(&self) -> u64 {
+        self.error_count.load(Ordering::Relaxed)
+    }
+
+    pub fn warning_count(&self) -> u64 {
+        self.warning_count.load(Ordering::Relaxed)
+    }
+}
+
+pub fn format_api_version(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        vk::api_version_major(version),
+        vk::api_version_minor(version),
+        vk::api_version_patch(version)
+    )
+}
+
+pub fn create_debug_messenger(
+    debug_utils: &DebugUtils,
+    state: &DebugMessengerState,
+) -> anyhow::Result<DebugUtilsMessengerEXT> {
+    let create_info = DebugUtilsMessengerCreateInfoEXT::builder()
+        .message_severity(
+            DebugUtilsMessageSeverityFlagsEXT::ERROR
+                | DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | DebugUtilsMessageSeverityFlagsEXT::INFO
+                | DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+        )
+        .message_type(
+            DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                | DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+        )
+        .pfn_user_callback(Some(debug_messenger_callback))
+        .user_data(state as *const DebugMessengerState as *mut c_void)
         .build();
 
     unsafe {
-        entry.create_instance(&create_info, None).context("failed to create instance")
+        debug_utils
+            .create_debug_utils_messenger(&create_info, None)
+            .context("failed to create debug messenger")
+    }
+}
+
+// routes validation layer output to the `log` crate
+unsafe extern "system" fn debug_messenger_callback(
+    message_severity: DebugUtilsMessageSeverityFlagsEXT,
+    message_types: DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const DebugUtilsMessengerCallbackDataEXT,
+    p_user_data: *mut c_void,
+) -> Bool32 {
+    let message = if p_callback_data.is_null() || (*p_callback_data).p_message.is_null() {
+        String::from("<no message>")
+    } else {
+        CStr::from_ptr((*p_callback_data).p_message)
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    if let Some(state) = p_user_data.cast::<DebugMessengerState>().as_ref() {
+        if let Some(filter) = &state.filter {
+            if !filter(message_severity, message_types, &message) {
+                return vk::FALSE;
+            }
+        }
+        match message_severity {
+            DebugUtilsMessageSeverityFlagsEXT::ERROR => {
+                state.error_count.fetch_add(1, Ordering::Relaxed);
+            }
+            DebugUtilsMessageSeverityFlagsEXT::WARNING => {
+                state.warning_count.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    match message_severity {
+        DebugUtilsMessageSeverityFlagsEXT::ERROR => log::error!("[{message_types:?}] {message}"),
+        DebugUtilsMessageSeverityFlagsEXT::WARNING => log::warn!("[{message_types:?}] {message}"),
+        DebugUtilsMessageSeverityFlagsEXT::INFO => log::info!("[{message_types:?}] {message}"),
+        _ => log::debug!("[{message_types:?}] {message}"),
+    }
+
+    // never abort the call that triggered the message
+    vk::FALSE
+}
+
+// names show up in RenderDoc, Nsight and validation messages, no-op without the
+// `validation_layers` feature
+pub fn set_object_name<T: vk::Handle>(vk: &Vk, object: T, name: &str) {
+    #[cfg(feature = "validation_layers")]
+    {
+        let name = CString::new(name.replace('\0', "")).unwrap();
+        let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+            .object_type(T::TYPE)
+            .object_handle(object.as_raw())
+            .object_name(&name)
+            .build();
+        let result = unsafe {
+            vk.debug_utils
+                .set_debug_utils_object_name(vk.device().handle(), &name_info)
+        };
+        if let Err(err) = result {
+            log::warn!("failed to name {:?} {name:?}: {err}", T::TYPE);
+        }
+    }
+    #[cfg(not(feature = "validation_layers"))]
+    let _ = (vk, object, name);
+}
+
+// groups the commands up to the matching `end_label` in capture tools, `color` is a hint
+// and all zeros lets the tool choose, no-op without the `validation_layers` feature
+pub fn begin_label(vk: &Vk, cmd: vk::CommandBuffer, label: &str, color: [f32; 4]) {
+    #[cfg(feature = "validation_layers")]
+    {
+        let label = CString::new(label.replace('\0', "")).unwrap();
+        let label = vk::DebugUtilsLabelEXT::builder()
+            .label_name(&label)
+            .color(color)
+            .build();
+        unsafe { vk.debug_utils.cmd_begin_debug_utils_label(cmd, &label) };
+    }
+    #[cfg(not(feature = "validation_layers"))]
+    let _ = (vk, cmd, label, color);
+}
+
+pub fn end_label(vk: &Vk, cmd: vk::CommandBuffer) {
+    #[cfg(feature = "validation_layers")]
+    unsafe {
+        vk.debug_utils.cmd_end_debug_utils_label(cmd)
+    };
+    #[cfg(not(feature = "validation_layers"))]
+    let _ = (vk, cmd);
+}
+
+// ends the label when dropped, keep it alive while recording the labeled commands
+pub struct DebugLabel<'a> {
+    vk: &'a Vk,
+    cmd: vk::CommandBuffer,
+}
+
+impl<'a> DebugLabel<'a> {
+    pub fn new(vk: &'a Vk, cmd: vk::CommandBuffer, label: &str, color: [f32; 4]) -> Self {
+        begin_label(vk, cmd, label, color);
+        Self { vk, cmd }
     }
 }
 
+impl Drop for DebugLabel<'_> {
+    fn drop(&mut self) {
+        end_label(self.vk, self.cmd);
+    }
+}
+
+// a device that meets the runtime's requirements, passed to `App::select_physical_device`
+#[derive(Clone, Debug)]
+pub struct PhysicalDeviceInfo {
+    pub physical_device: PhysicalDevice,
+    pub properties: vk::PhysicalDeviceProperties,
+    pub features: PhysicalDeviceFeatures,
+    pub name: String,
+}
+
+// prefers discrete over integrated gpus, then larger maximum image dimensions, ties go
+// to the device enumerated first
+pub fn select_best_physical_device(
+    candidates: &[PhysicalDeviceInfo],
+) -> anyhow::Result<&PhysicalDeviceInfo> {
+    let score = |info: &PhysicalDeviceInfo| {
+        // bias towards discrete gpus
+        let type_score = match info.properties.device_type {
+            PhysicalDeviceType::DISCRETE_GPU => 1000,
+            PhysicalDeviceType::INTEGRATED_GPU => 100,
+            _ => 0,
+        };
+        // prefer device that support larger image dimensions
+        type_score + info.properties.limits.max_image_dimension2_d
+    };
+    // `max_by_key` returns the last of equal maxima
+    candidates
+        .iter()
+        .rev()
+        .max_by_key(|e| score(e))
+        .context("no physical device candidates")
+}
+
+// `select` picks among the devices that support `api_version`, dynamic rendering, the
+// required extensions and features
 pub fn select_physical_device(
     instance: &Instance,
     required_device_extensions: &Vec<CString>,
+    api_version: u32,
+    required_features: &PhysicalDeviceFeatures,
+    select: &dyn Fn(&[PhysicalDeviceInfo]) -> anyhow::Result<&PhysicalDeviceInfo>,
 ) -> anyhow::Result<PhysicalDevice> {
     let physical_devices = unsafe {
         instance
             .enumerate_physical_devices()
-            .expect("physical devices should be available.")
+            .context("failed to enumerate physical devices")?
+    };
+    if physical_devices.is_empty() {
+        bail!("no vulkan capable physical devices found");
+    }
+    let mut candidates = Vec::<PhysicalDeviceInfo>::new();
+    // why each device was skipped, part of the error if none is left
+    let mut skipped = Vec::<String>::new();
+    let mut skip = |name: &str, reason: String| {
+        log::info!("skipping {name:?}, {reason}");
+        skipped.push(format!("{name}: {reason}"));
     };
-    let mut candidates = BTreeMap::<u32, PhysicalDevice>::new();
     for physical_device in physical_devices {
-        let mut score: u32 = 0;
         let properties = unsafe { instance.get_physical_device_properties(physical_device) };
-        let _features = unsafe { instance.get_physical_device_features(physical_device) };
+        let name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }
+            .to_string_lossy()
+            .into_owned();
+        if properties.api_version < api_version {
+            skip(
+                &name,
+                format!(
+                    "it only supports vulkan {}",
+                    format_api_version(properties.api_version)
+                ),
+            );
+            continue;
+        }
 
-        // bias towards discrete gpus
-        score += match properties.device_type {
-            PhysicalDeviceType::DISCRETE_GPU => 1000,
-            PhysicalDeviceType::INTEGRATED_GPU => 100,
-            _ => 0,
+        // everything renders through dynamic rendering, devices without it are unusable
+        let mut dynamic_rendering = PhysicalDeviceDynamicRenderingFeaturesKHR::default();
+        let mut features = PhysicalDeviceFeatures2::builder()
+            .push_next(&mut dynamic_rendering)
+            .build();
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+        if dynamic_rendering.dynamic_rendering != vk::TRUE {
+            skip(&name, "dynamic rendering is not supported".to_string());
+            continue;
+        }
+        let missing_features = missing_features(required_features, &features.features);
+        if !missing_features.is_empty() {
+            skip(
+                &name,
+                format!("missing required features {}", missing_features.join(", ")),
+            );
+            continue;
+        }
+
+        let actual_device_extensions: HashSet<String> = unsafe {
+            instance
+                .enumerate_device_extension_properties(physical_device)
+                .context("physical device extensions should be enumerable.")?
+                .iter()
+                .map(|e| {
+                    CStr::from_ptr(e.extension_name.as_ptr())
+                        .to_str()
+                        .unwrap()
+                        .to_string()
+                })
+                .collect()
         };
-        // prefer device that support larger image dimensions
-        score += properties.limits.max_image_dimension2_d;
+        let missing_extensions: Vec<_> = required_device_extensions
+            .iter()
+            .map(|e| e.to_str().unwrap())
+            .filter(|e| !actual_device_extensions.contains(*e))
+            .collect();
+        if !missing_extensions.is_empty() {
+            skip(
+                &name,
+                format!(
+                    "missing required extensions {}",
+                    missing_extensions.join(", ")
+                ),
+            );
+            continue;
+        }
+
+        candidates.push(PhysicalDeviceInfo {
+            physical_device,
+            properties,
+            features: features.features,
+            name,
+        });
+    }
+
+    if candidates.is_empty() {
+        bail!(
+            "no physical device supports vulkan {}, dynamic rendering and the required extensions and features ({})",
+            format_api_version(api_version),
+            skipped.join("; ")
+        );
+    }
+    let selected = select(&candidates)?;
+    log::info!("selected physical device {:?}", selected.name);
+
+    Ok(selected.physical_device)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueFamilyIndices {
+    // supports GRAPHICS and COMPUTE
+    pub graphics: u32,
+    // may be the same as `graphics`
+    pub present: u32,
+    // dedicated TRANSFER family without GRAPHICS, usually backed by a DMA engine
+    pub transfer: Option<u32>,
+}
+
+impl QueueFamilyIndices {
+    pub fn is_separate_present(&self) -> bool {
+        self.graphics != self.present
+    }
+
+    pub fn unique(&self) -> Vec<u32> {
+        let mut indices = vec![self.graphics];
+        for index in [Some(self.present), self.transfer].into_iter().flatten() {
+            if !indices.contains(&index) {
+                indices.push(index);
+            }
+        }
+        indices
+    }
+}
+
+pub fn find_queue_family_indices(
+    instance: &Instance,
+    khr_surface: &ash::extensions::khr::Surface,
+    physical_device: PhysicalDevice,
+    // without a surface (headless) `present` is always the graphics family
+    surface: Option<SurfaceKHR>,
+) -> anyhow::Result<QueueFamilyIndices> {
+    let queue_families =
+        unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+    let present_support = match surface {
+        Some(surface) => (0..queue_families.len() as u32)
+            .map(|index| unsafe {
+                khr_surface
+                    .get_physical_device_surface_support(physical_device, index, surface)
+                    .context("failed to query queue family present support")
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        None => vec![true; queue_families.len()],
+    };
+    choose_queue_family_indices(&queue_families, &present_support)
+}
+
+// `present_support` has an entry per family, split out of `find_queue_family_indices` so
+// the choice can be tested without a device
+pub fn choose_queue_family_indices(
+    queue_families: &[vk::QueueFamilyProperties],
+    present_support: &[bool],
+) -> anyhow::Result<QueueFamilyIndices> {
+    let supports_present = |index: u32| present_support[index as usize];
+    let graphics_families: Vec<u32> = queue_families
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| {
+            e.queue_flags
+                .contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+        })
+        .map(|(index, _)| index as u32)
+        .collect();
+
+    // prefer a transfer only family, then one that can at least not do graphics
+    let transfer_families = |excluded: vk::QueueFlags| {
+        queue_families
+            .iter()
+            .position(|e| {
+                e.queue_flags.contains(vk::QueueFlags::TRANSFER)
+                    && !e.queue_flags.intersects(excluded)
+            })
+            .map(|index| index as u32)
+    };
+    let transfer = transfer_families(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE)
+        .or_else(|| transfer_families(vk::QueueFlags::GRAPHICS));
 
-        candidates.insert(score, physical_device);
+    // prefer a single family that can do everything
+    if let Some(index) = graphics_families
+        .iter()
+        .copied()
+        .find(|e| supports_present(*e))
+    {
+        return Ok(QueueFamilyIndices {
+            graphics: index,
+            present: index,
+            transfer,
+        });
     }
 
-    let physical_device = candidates
-        .last_entry()
-        .context("suitable device should be found.")?
-        .remove();
+    let Some(graphics) = graphics_families.first().copied() else {
+        bail!("failed to find queue family that supports GRAPHICS | COMPUTE");
+    };
+    let Some(present) = (0..queue_families.len() as u32).find(|e| supports_present(*e)) else {
+        bail!("failed to find queue family that supports presenting to the surface");
+    };
+
+    Ok(QueueFamilyIndices {
+        graphics,
+        present,
+        transfer,
+    })
+}
+
+// PhysicalDeviceFeatures field names in declaration order
+const FEATURE_NAMES: [&str; 55] = [
+    "robust_buffer_access",
+    "full_draw_index_uint32",
+    "image_cube_array",
+    "independent_blend",
+    "geometry_shader",
+    "tessellation_shader",
+    "sample_rate_shading",
+    "dual_src_blend",
+    "logic_op",
+    "multi_draw_indirect",
+    "draw_indirect_first_instance",
+    "depth_clamp",
+    "depth_bias_clamp",
+    "fill_mode_non_solid",
+    "depth_bounds",
+    "wide_lines",
+    "large_points",
+    "alpha_to_one",
+    "multi_viewport",
+    "sampler_anisotropy",
+    "texture_compression_etc2",
+    "texture_compression_astc_ldr",
+    "texture_compression_bc",
+    "occlusion_query_precise",
+    "pipeline_statistics_query",
+    "vertex_pipeline_stores_and_atomics",
+    "fragment_stores_and_atomics",
+    "shader_tessellation_and_geometry_point_size",
+    "shader_image_gather_extended",
+    "shader_storage_image_extended_formats",
+    "shader_storage_image_multisample",
+    "shader_storage_image_read_without_format",
+    "shader_storage_image_write_without_format",
+    "shader_uniform_buffer_array_dynamic_indexing",
+    "shader_sampled_image_array_dynamic_indexing",
+    "shader_storage_buffer_array_dynamic_indexing",
+    "shader_storage_image_array_dynamic_indexing",
+    "shader_clip_distance",
+    "shader_cull_distance",
+    "shader_float64",
+    "shader_int64",
+    "shader_int16",
+    "shader_resource_residency",
+    "shader_resource_min_lod",
+    "sparse_binding",
+    "sparse_residency_buffer",
+    "sparse_residency_image2_d",
+    "sparse_residency_image3_d",
+    "sparse_residency2_samples",
+    "sparse_residency4_samples",
+    "sparse_residency8_samples",
+    "sparse_residency16_samples",
+    "sparse_residency_aliased",
+    "variable_multisample_rate",
+    "inherited_queries",
+];
+
+// names of the features set in `required` but not in `available`
+fn missing_features(
+    required: &PhysicalDeviceFeatures,
+    available: &PhysicalDeviceFeatures,
+) -> Vec<&'static str> {
+    // PhysicalDeviceFeatures is a repr(C) struct made only of Bool32 fields
+    let as_slice = |features: &PhysicalDeviceFeatures| unsafe {
+        std::slice::from_raw_parts(
+            (features as *const PhysicalDeviceFeatures).cast::<Bool32>(),
+            FEATURE_NAMES.len(),
+        )
+    };
+    const {
+        assert!(
+            std::mem::size_of::<PhysicalDeviceFeatures>()
+                == FEATURE_NAMES.len() * std::mem::size_of::<Bool32>()
+        )
+    };
+    as_slice(required)
+        .iter()
+        .zip(as_slice(available))
+        .zip(FEATURE_NAMES)
+        .filter(|((required, available), _)| **required == vk::TRUE && **available != vk::TRUE)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+// optional device capabilities, enabled only when the physical device supports them
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OptionalDeviceSupport {
+    // VK_EXT_device_fault
+    pub device_fault: bool,
+    // VK_KHR_present_id + VK_KHR_present_wait
+    pub present_wait: bool,
+    // VK_EXT_robustness2, the extension is enabled if any of these are set
+    pub robust_buffer_access2: bool,
+    pub robust_image_access2: bool,
+    pub null_descriptor: bool,
+    // VK_EXT_subgroup_size_control
+    pub subgroup_size_control: bool,
+    // core in vulkan 1.2, only queried on devices that report 1.2
+    pub timeline_semaphore: bool,
+    // VK_KHR_synchronization2
+    pub synchronization2: bool,
+    // VK_EXT_swapchain_maintenance1, also needs VK_EXT_surface_maintenance1 on the instance
+    pub swapchain_maintenance1: bool,
+    // core in vulkan 1.1
+    pub multiview: bool,
+    // VK_KHR_swapchain_mutable_format, also needs VK_KHR_image_format_list before vulkan 1.2
+    pub swapchain_mutable_format: bool,
+    // VK_KHR_image_format_list, core in vulkan 1.2
+    pub image_format_list: bool,
+}
+
+impl OptionalDeviceSupport {
+    fn robustness2(&self) -> bool {
+        self.robust_buffer_access2 || self.robust_image_access2 || self.null_descriptor
+    }
+}
 
-    let actual_device_extensions: HashSet<String> = unsafe {
+pub fn query_optional_device_support(
+    instance: &Instance,
+    physical_device: PhysicalDevice,
+    api_version: u32,
+) -> anyhow::Result<OptionalDeviceSupport> {
+    let available_extensions: HashSet<CString> = unsafe {
         instance
             .enumerate_device_extension_properties(physical_device)
             .context("physical device extensions should be enumerable.")?
             .iter()
-            .map(|e| {
-                CStr::from_ptr(e.extension_name.as_ptr())
-                    .to_str()
-                    .unwrap()
-                    .to_string()
-            })
+            .map(|e| CString::from(CStr::from_ptr(e.extension_name.as_ptr())))
             .collect()
     };
+    let is_available = |name: &CStr| available_extensions.contains(name);
 
-    let mut count = 0;
-    for required_extension in required_device_extensions.iter() {
-        let key = required_extension.to_str().unwrap().to_string();
-        if actual_device_extensions.contains(&key) {
-            count += 1;
-        }
+    let mut fault_features = PhysicalDeviceFaultFeaturesEXT::default();
+    let mut present_id_features = vk::PhysicalDevicePresentIdFeaturesKHR::default();
+    let mut present_wait_features = vk::PhysicalDevicePresentWaitFeaturesKHR::default();
+    let mut robustness2_features = vk::PhysicalDeviceRobustness2FeaturesEXT::default();
+    let mut subgroup_size_control_features =
+        vk::PhysicalDeviceSubgroupSizeControlFeaturesEXT::default();
+    let mut vulkan12_features = vk::PhysicalDeviceVulkan12Features::default();
+    let mut synchronization2_features = vk::PhysicalDeviceSynchronization2FeaturesKHR::default();
+    let mut swapchain_maintenance1_features =
+        vk::PhysicalDeviceSwapchainMaintenance1FeaturesEXT::default();
+    let mut multiview_features = vk::PhysicalDeviceMultiviewFeatures::default();
+    let mut features = PhysicalDeviceFeatures2::builder().push_next(&mut multiview_features);
+    if is_available(ExtDeviceFaultFn::name()) {
+        features = features.push_next(&mut fault_features);
     }
-    if count != required_device_extensions.len() {
-        bail!("device is missing required extensions")
+    let present_wait_available = is_available(vk::KhrPresentIdFn::name())
+        && is_available(ash::extensions::khr::PresentWait::name());
+    if present_wait_available {
+        features = features
+            .push_next(&mut present_id_features)
+            .push_next(&mut present_wait_features);
     }
+    if is_available(vk::ExtRobustness2Fn::name()) {
+        features = features.push_next(&mut robustness2_features);
+    }
+    if is_available(vk::ExtSubgroupSizeControlFn::name()) {
+        features = features.push_next(&mut subgroup_size_control_features);
+    }
+    if is_available(ash::extensions::khr::Synchronization2::name()) {
+        features = features.push_next(&mut synchronization2_features);
+    }
+    if is_available(vk::ExtSwapchainMaintenance1Fn::name()) {
+        features = features.push_next(&mut swapchain_maintenance1_features);
+    }
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    if properties.api_version.min(api_version) >= API_VERSION_1_2 {
+        features = features.push_next(&mut vulkan12_features);
+    }
+    let mut features = features.build();
+    unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+    // no feature structs, the extensions being available is enough
+    let image_format_list = properties.api_version.min(api_version) >= API_VERSION_1_2
+        || is_available(vk::KhrImageFormatListFn::name());
+    let swapchain_mutable_format =
+        is_available(vk::KhrSwapchainMutableFormatFn::name()) && image_format_list;
 
-    Ok(physical_device)
+    Ok(OptionalDeviceSupport {
+        device_fault: fault_features.device_fault == vk::TRUE,
+        present_wait: present_id_features.present_id == vk::TRUE
+            && present_wait_features.present_wait == vk::TRUE,
+        robust_buffer_access2: robustness2_features.robust_buffer_access2 == vk::TRUE,
+        robust_image_access2: robustness2_features.robust_image_access2 == vk::TRUE,
+        null_descriptor: robustness2_features.null_descriptor == vk::TRUE,
+        subgroup_size_control: subgroup_size_control_features.subgroup_size_control == vk::TRUE,
+        timeline_semaphore: vulkan12_features.timeline_semaphore == vk::TRUE,
+        synchronization2: synchronization2_features.synchronization2 == vk::TRUE,
+        swapchain_maintenance1: swapchain_maintenance1_features.swapchain_maintenance1 == vk::TRUE,
+        multiview: multiview_features.multiview == vk::TRUE,
+        swapchain_mutable_format,
+        image_format_list,
+    })
 }
 
-pub fn find_queue_family_indices(
+#[derive(Clone, Copy, Debug)]
+pub struct SubgroupProperties {
+    // default subgroup size
+    pub subgroup_size: u32,
+    pub supported_stages: vk::ShaderStageFlags,
+    pub supported_operations: vk::SubgroupFeatureFlags,
+    pub quad_operations_in_all_stages: bool,
+    // (min, max) sizes a compute pipeline can request, only with VK_EXT_subgroup_size_control
+    pub size_control: Option<(u32, u32)>,
+}
+
+pub fn query_subgroup_properties(
     instance: &Instance,
     physical_device: PhysicalDevice,
+    subgroup_size_control: bool,
+) -> SubgroupProperties {
+    let mut subgroup = vk::PhysicalDeviceSubgroupProperties::default();
+    let mut size_control = vk::PhysicalDeviceSubgroupSizeControlPropertiesEXT::default();
+    let mut properties = vk::PhysicalDeviceProperties2::builder().push_next(&mut subgroup);
+    if subgroup_size_control {
+        properties = properties.push_next(&mut size_control);
+    }
+    let mut properties = properties.build();
+    unsafe { instance.get_physical_device_properties2(physical_device, &mut properties) };
+
+    let size_control_stages = size_control.required_subgroup_size_stages;
+    SubgroupProperties {
+        subgroup_size: subgroup.subgroup_size,
+        supported_stages: subgroup.supported_stages,
+        supported_operations: subgroup.supported_operations,
+        quad_operations_in_all_stages: subgroup.quad_operations_in_all_stages == vk::TRUE,
+        size_control: (subgroup_size_control
+            && size_control_stages.contains(vk::ShaderStageFlags::COMPUTE))
+        .then_some((
+            size_control.min_subgroup_size,
+            size_control.max_subgroup_size,
+        )),
+    }
+}
+
+// 0 when multiview isn't supported
+pub fn query_max_multiview_view_count(
+    instance: &Instance,
+    physical_device: PhysicalDevice,
+    multiview: bool,
 ) -> u32 {
+    if !multiview {
+        return 0;
+    }
+
+    let mut multiview_properties = vk::PhysicalDeviceMultiviewProperties::default();
+    let mut properties = vk::PhysicalDeviceProperties2::builder()
+        .push_next(&mut multiview_properties)
+        .build();
+    unsafe { instance.get_physical_device_properties2(physical_device, &mut properties) };
+    multiview_properties.max_multiview_view_count
+}
+
+// sparse residency needs both the device features and a queue that can do sparse binding
+pub fn is_sparse_residency_supported(
+    instance: &Instance,
+    physical_device: PhysicalDevice,
+    queue_family_idx: u32,
+) -> bool {
+    let features = unsafe { instance.get_physical_device_features(physical_device) };
     let queue_families =
         unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
-    for (index, queue_family) in queue_families.into_iter().enumerate() {
-        if queue_family
-            .queue_flags
-            .contains(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE) // assume present is supported
-        {
-            return index as u32;
-        }
+    let queue_supports_sparse_binding = queue_families
+        .get(queue_family_idx as usize)
+        .is_some_and(|e| e.queue_flags.contains(vk::QueueFlags::SPARSE_BINDING));
+
+    features.sparse_binding == vk::TRUE
+        && features.sparse_residency_image2_d == vk::TRUE
+        && queue_supports_sparse_binding
+}
+
+pub fn load_device_fault_fn(instance: &Instance, device: &Device) -> ExtDeviceFaultFn {
+    ExtDeviceFaultFn::load(|name| unsafe {
+        std::mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+    })
+}
+
+pub fn log_device_fault_info(device: &Device, device_fault: &ExtDeviceFaultFn) {
+    let mut counts = DeviceFaultCountsEXT::default();
+    let result = unsafe {
+        (device_fault.get_device_fault_info_ext)(device.handle(), &mut counts, std::ptr::null_mut())
+    };
+    if result != vk::Result::SUCCESS {
+        log::error!("failed to query device fault counts: {result}");
+        return;
+    }
+
+    let mut address_infos =
+        vec![DeviceFaultAddressInfoEXT::default(); counts.address_info_count as usize];
+    let mut vendor_infos =
+        vec![DeviceFaultVendorInfoEXT::default(); counts.vendor_info_count as usize];
+    // vendor binary dumps are opaque, skip them
+    counts.vendor_binary_size = 0;
+    let mut info = DeviceFaultInfoEXT {
+        p_address_infos: address_infos.as_mut_ptr(),
+        p_vendor_infos: vendor_infos.as_mut_ptr(),
+        ..Default::default()
+    };
+    let result = unsafe {
+        (device_fault.get_device_fault_info_ext)(device.handle(), &mut counts, &mut info)
+    };
+    if result != vk::Result::SUCCESS && result != vk::Result::INCOMPLETE {
+        log::error!("failed to query device fault info: {result}");
+        return;
     }
 
-    panic!("failed to find queue family that supports GRAPHICS, COMPUTE and PRESENT")
+    let description = unsafe { CStr::from_ptr(info.description.as_ptr()) };
+    log::error!("device fault: {}", description.to_string_lossy());
+    for address_info in address_infos
+        .iter()
+        .take(counts.address_info_count as usize)
+    {
+        log::error!(
+            "  address fault: {:?} at {:#x} (precision {:#x})",
+            address_info.address_type,
+            address_info.reported_address,
+            address_info.address_precision
+        );
+    }
+    for vendor_info in vendor_infos.iter().take(counts.vendor_info_count as usize) {
+        let description = unsafe { CStr::from_ptr(vendor_info.description.as_ptr()) };
+        log::error!(
+            "  vendor fault: {} (code {:#x}, data {:#x})",
+            description.to_string_lossy(),
+            vendor_info.vendor_fault_code,
+            vendor_info.vendor_fault_data
+        );
+    }
 }
 
 pub fn create_device(
     instance: &Instance,
     physical_device: PhysicalDevice,
-    queue_family_idx: u32,
+    queue_family_indices: &QueueFamilyIndices,
     required_device_extensions: &Vec<CString>,
+    enabled_features: &PhysicalDeviceFeatures,
+    optional_support: OptionalDeviceSupport,
+    api_version: u32,
 ) -> anyhow::Result<Device> {
-    let queue_create_infos = [DeviceQueueCreateInfo::builder()
-        .queue_family_index(queue_family_idx)
-        .queue_priorities(&[1.0])
-        .build()];
+    let queue_create_infos: Vec<_> = queue_family_indices
+        .unique()
+        .into_iter()
+        .map(|e| {
+            DeviceQueueCreateInfo::builder()
+                .queue_family_index(e)
+                .queue_priorities(&[1.0])
+                .build()
+        })
+        .collect();
 
-    let physical_device_features = PhysicalDeviceFeatures::default();
     // enable dynamic rendering
     let mut dynamic_rendering = PhysicalDeviceDynamicRenderingFeaturesKHR::builder()
         .dynamic_rendering(true)
         .build();
 
-    let required_device_extensions_ptr: Vec<_> = required_device_extensions
+    let mut device_fault = PhysicalDeviceFaultFeaturesEXT::builder()
+        .device_fault(true)
+        .build();
+    let mut present_id = vk::PhysicalDevicePresentIdFeaturesKHR::builder()
+        .present_id(true)
+        .build();
+    let mut present_wait = vk::PhysicalDevicePresentWaitFeaturesKHR::builder()
+        .present_wait(true)
+        .build();
+    let mut robustness2 = vk::PhysicalDeviceRobustness2FeaturesEXT::builder()
+        .robust_buffer_access2(optional_support.robust_buffer_access2)
+        .robust_image_access2(optional_support.robust_image_access2)
+        .null_descriptor(optional_support.null_descriptor)
+        .build();
+    let mut subgroup_size_control = vk::PhysicalDeviceSubgroupSizeControlFeaturesEXT::builder()
+        .subgroup_size_control(true)
+        .build();
+    let mut vulkan12 = vk::PhysicalDeviceVulkan12Features::builder()
+        .timeline_semaphore(true)
+        .build();
+    let mut synchronization2 = vk::PhysicalDeviceSynchronization2FeaturesKHR::builder()
+        .synchronization2(true)
+        .build();
+    let mut swapchain_maintenance1 = vk::PhysicalDeviceSwapchainMaintenance1FeaturesEXT::builder()
+        .swapchain_maintenance1(true)
+        .build();
+    let mut multiview = vk::PhysicalDeviceMultiviewFeatures::builder()
+        .multiview(true)
+        .build();
+
+    let mut required_device_extensions_ptr: Vec<_> = required_device_extensions
         .iter()
         .map(|e| e.as_c_str().as_ptr())
         .collect();
-    let device_create_info = DeviceCreateInfo::builder()
+    if optional_support.device_fault {
+        required_device_extensions_ptr.push(ExtDeviceFaultFn::name().as_ptr());
+    }
+    if optional_support.present_wait {
+        required_device_extensions_ptr.push(vk::KhrPresentIdFn::name().as_ptr());
+        required_device_extensions_ptr.push(ash::extensions::khr::PresentWait::name().as_ptr());
+    }
+    if optional_support.robustness2() {
+        required_device_extensions_ptr.push(vk::ExtRobustness2Fn::name().as_ptr());
+    }
+    if optional_support.subgroup_size_control {
+        required_device_extensions_ptr.push(vk::ExtSubgroupSizeControlFn::name().as_ptr());
+    }
+    if optional_support.synchronization2 {
+        required_device_extensions_ptr
+            .push(ash::extensions::khr::Synchronization2::name().as_ptr());
+    }
+    if optional_support.swapchain_maintenance1 {
+        required_device_extensions_ptr.push(vk::ExtSwapchainMaintenance1Fn::name().as_ptr());
+    }
+    if optional_support.swapchain_mutable_format {
+        required_device_extensions_ptr.push(vk::KhrSwapchainMutableFormatFn::name().as_ptr());
+    }
+    if optional_support.image_format_list && api_version < API_VERSION_1_2 {
+        required_device_extensions_ptr.push(vk::KhrImageFormatListFn::name().as_ptr());
+    }
+    let mut device_create_info = DeviceCreateInfo::builder()
         .queue_create_infos(&queue_create_infos)
-        .enabled_features(&physical_device_features)
+        .enabled_features(enabled_features)
         .enabled_extension_names(required_device_extensions_ptr.as_slice())
-        .push_next(&mut dynamic_rendering)
-        .build();
+        .push_next(&mut dynamic_rendering);
+    if optional_support.device_fault {
+        device_create_info = device_create_info.push_next(&mut device_fault);
+    }
+    if optional_support.present_wait {
+        device_create_info = device_create_info
+            .push_next(&mut present_id)
+            .push_next(&mut present_wait);
+    }
+    if optional_support.robustness2() {
+        device_create_info = device_create_info.push_next(&mut robustness2);
+    }
+    if optional_support.subgroup_size_control {
+        device_create_info = device_create_info.push_next(&mut subgroup_size_control);
+    }
+    if optional_support.timeline_semaphore {
+        device_create_info = device_create_info.push_next(&mut vulkan12);
+    }
+    if optional_support.synchronization2 {
+        device_create_info = device_create_info.push_next(&mut synchronization2);
+    }
+    if optional_support.swapchain_maintenance1 {
+        device_create_info = device_create_info.push_next(&mut swapchain_maintenance1);
+    }
+    if optional_support.multiview {
+        device_create_info = device_create_info.push_next(&mut multiview);
+    }
+    let device_create_info = device_create_info.build();
     unsafe {
-        Ok(instance
+        instance
             .create_device(physical_device, &device_create_info, None)
-            .expect("create_device successful."))
+            .context("failed to create logical device")
     }
 }
 
 pub fn create_surface(
     entry: &Entry,
     instance: &Instance,
-    window: &(impl HasRawDisplayHandle + HasRawWindowHandle),
+    display_handle: &dyn HasRawDisplayHandle,
+    window_handle: &dyn HasRawWindowHandle,
 ) -> anyhow::Result<SurfaceKHR> {
     let vk_surface = unsafe {
         ash_window::create_surface(
             entry,
             instance,
-            window.raw_display_handle(),
-            window.raw_window_handle(),
+            display_handle.raw_display_handle(),
+            window_handle.raw_window_handle(),
             None,
         )?
     };
     Ok(vk_surface)
-}
\ No newline at end of file
+}
+
+pub fn enumerate_instance_extensions(entry: &Entry) -> anyhow::Result<HashSet<CString>> {
+    Ok(entry
+        .enumerate_instance_extension_properties(None)
+        .context("instance extensions should be enumerable.")?
+        .iter()
+        .map(|e| unsafe { CString::from(CStr::from_ptr(e.extension_name.as_ptr())) })
+        .collect())
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PresentScalingCapabilities {
+    pub supported_scaling: vk::PresentScalingFlagsEXT,
+    pub supported_gravity_x: vk::PresentGravityFlagsEXT,
+    pub supported_gravity_y: vk::PresentGravityFlagsEXT,
+    pub min_scaled_image_extent: vk::Extent2D,
+    pub max_scaled_image_extent: vk::Extent2D,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SurfaceCapabilities {
+    pub capabilities: vk::SurfaceCapabilitiesKHR,
+    // only present with VK_EXT_surface_maintenance1, specific to the queried present mode
+    pub present_scaling: Option<PresentScalingCapabilities>,
+}
+
+// uses VK_KHR_get_surface_capabilities2 when it was enabled on the instance so extension
+// structs can be chained, otherwise only the base capabilities are filled in
+pub fn query_surface_capabilities(
+    khr_surface: &ash::extensions::khr::Surface,
+    khr_get_surface_capabilities2: Option<&ash::extensions::khr::GetSurfaceCapabilities2>,
+    surface_maintenance1: bool,
+    physical_device: PhysicalDevice,
+    surface: SurfaceKHR,
+    present_mode: vk::PresentModeKHR,
+) -> anyhow::Result<SurfaceCapabilities> {
+    let Some(khr_get_surface_capabilities2) = khr_get_surface_capabilities2 else {
+        let capabilities = unsafe {
+            khr_surface
+                .get_physical_device_surface_capabilities(physical_device, surface)
+                .context("failed to query surface capabilities")?
+        };
+        return Ok(SurfaceCapabilities {
+            capabilities,
+            present_scaling: None,
+        });
+    };
+
+    let mut surface_present_mode = vk::SurfacePresentModeEXT::builder()
+        .present_mode(present_mode)
+        .build();
+    let mut surface_info = vk::PhysicalDeviceSurfaceInfo2KHR::builder().surface(surface);
+    if surface_maintenance1 {
+        surface_info = surface_info.push_next(&mut surface_present_mode);
+    }
+    let surface_info = surface_info.build();
+
+    let mut present_scaling = vk::SurfacePresentScalingCapabilitiesEXT::default();
+    let mut capabilities2 = vk::SurfaceCapabilities2KHR::builder();
+    if surface_maintenance1 {
+        capabilities2 = capabilities2.push_next(&mut present_scaling);
+    }
+    let mut capabilities2 = capabilities2.build();
+    // ash's wrapper doesn't take an output chain, call through the function pointer
+    unsafe {
+        (khr_get_surface_capabilities2
+            .fp()
+            .get_physical_device_surface_capabilities2_khr)(
+            physical_device,
+            &surface_info,
+            &mut capabilities2,
+        )
+        .result()
+        .context("failed to query surface capabilities")?
+    };
+
+    Ok(SurfaceCapabilities {
+        capabilities: capabilities2.surface_capabilities,
+        present_scaling: surface_maintenance1.then_some(PresentScalingCapabilities {
+            supported_scaling: present_scaling.supported_present_scaling,
+            supported_gravity_x: present_scaling.supported_present_gravity_x,
+            supported_gravity_y: present_scaling.supported_present_gravity_y,
+            min_scaled_image_extent: present_scaling.min_scaled_image_extent,
+            max_scaled_image_extent: present_scaling.max_scaled_image_extent,
+        }),
+    })
+}
+
+// picks the first preferred format supported by the surface, otherwise whatever the surface lists first
+pub fn choose_surface_format(
+    khr_surface: &ash::extensions::khr::Surface,
+    physical_device: PhysicalDevice,
+    surface: SurfaceKHR,
+    preferred: &[(Format, ColorSpaceKHR)],
+) -> anyhow::Result<SurfaceFormatKHR> {
+    let available_formats = unsafe {
+        khr_surface
+            .get_physical_device_surface_formats(physical_device, surface)
+            .context("failed to query surface formats")?
+    };
+
+    let preferred_format = preferred.iter().find_map(|(format, color_space)| {
+        available_formats
+            .iter()
+            .find(|e| e.format == *format && e.color_space == *color_space)
+    });
+
+    if let Some(surface_format) = preferred_format {
+        return Ok(*surface_format);
+    }
+
+    let surface_format = available_formats
+        .first()
+        .context("surface does not report any formats")?;
+    log::warn!(
+        "preferred surface formats {preferred:?} not supported, using {:?} {:?}",
+        surface_format.format,
+        surface_format.color_space
+    );
+    Ok(*surface_format)
+}
+
+// only covers the uncompressed formats a surface can realistically report
+pub fn is_srgb_format(format: Format) -> bool {
+    matches!(
+        format,
+        Format::R8_SRGB
+            | Format::R8G8_SRGB
+            | Format::R8G8B8_SRGB
+            | Format::B8G8R8_SRGB
+            | Format::R8G8B8A8_SRGB
+            | Format::B8G8R8A8_SRGB
+            | Format::A8B8G8R8_SRGB_PACK32
+    )
+}
+
+// the _SRGB format with the same layout as a UNORM one and vice versa
+pub fn srgb_unorm_counterpart(format: Format) -> Option<Format> {
+    const PAIRS: [(Format, Format); 7] = [
+        (Format::R8_UNORM, Format::R8_SRGB),
+        (Format::R8G8_UNORM, Format::R8G8_SRGB),
+        (Format::R8G8B8_UNORM, Format::R8G8B8_SRGB),
+        (Format::B8G8R8_UNORM, Format::B8G8R8_SRGB),
+        (Format::R8G8B8A8_UNORM, Format::R8G8B8A8_SRGB),
+        (Format::B8G8R8A8_UNORM, Format::B8G8R8A8_SRGB),
+        (Format::A8B8G8R8_UNORM_PACK32, Format::A8B8G8R8_SRGB_PACK32),
+    ];
+    PAIRS.iter().find_map(|&(unorm, srgb)| {
+        if format == unorm {
+            Some(srgb)
+        } else if format == srgb {
+            Some(unorm)
+        } else {
+            None
+        }
+    })
+}
+
+// only sRGB/UNORM reinterpretation is considered, other formats of the same compatibility
+// class are rejected even though vulkan would allow them
+pub fn is_view_format_compatible(image_format: Format, view_format: Format) -> bool {
+    image_format == view_format || srgb_unorm_counterpart(image_format) == Some(view_format)
+}
+
+pub fn format_has_stencil(format: Format) -> bool {
+    matches!(
+        format,
+        Format::S8_UINT
+            | Format::D16_UNORM_S8_UINT
+            | Format::D24_UNORM_S8_UINT
+            | Format::D32_SFLOAT_S8_UINT
+    )
+}
+
+// combined formats need both aspects in barriers and views
+pub fn depth_stencil_aspect_mask(format: Format) -> vk::ImageAspectFlags {
+    match format {
+        Format::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        _ if format_has_stencil(format) => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::DEPTH,
+    }
+}
+
+// the layout depth/stencil attachments of every format are rendered in,
+// DEPTH_ATTACHMENT_OPTIMAL would need separateDepthStencilLayouts, which isn't enabled
+pub const DEPTH_STENCIL_ATTACHMENT_LAYOUT: vk::ImageLayout =
+    vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL;
+
+// cleared on load and kept or discarded per `store_op`, DONT_CARE suits transient attachments
+// nothing reads after the pass, for a format with a stencil aspect the same info must also be
+// passed as the stencil attachment of the rendering info
+pub fn depth_stencil_attachment_info(
+    view: vk::ImageView,
+    clear_depth: f32,
+    clear_stencil: u32,
+    store_op: vk::AttachmentStoreOp,
+) -> vk::RenderingAttachmentInfoKHR {
+    vk::RenderingAttachmentInfoKHR::builder()
+        .image_view(view)
+        .image_layout(DEPTH_STENCIL_ATTACHMENT_LAYOUT)
+        .load_op(vk::AttachmentLoadOp::CLEAR)
+        .store_op(store_op)
+        .clear_value(vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue {
+                depth: clear_depth,
+                stencil: clear_stencil,
+            },
+        })
+        .build()
+}
+
+// copies `data` into a new device local buffer through a host visible staging buffer,
+// blocks until the transfer is complete
+pub fn upload_to_device_local_buffer<T: Copy>(
+    vk: &Vk,
+    data: &[T],
+    usage: vk::BufferUsageFlags,
+) -> anyhow::Result<(vk::Buffer, Allocation)> {
+    let size = std::mem::size_of_val(data) as u64;
+
+    let (staging_buffer, staging_allocation) = vk.allocate_buffer(
+        size,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        MemoryLocation::CpuToGpu,
+    )?;
+    let mapped_ptr = match staging_allocation.mapped_ptr() {
+        Some(ptr) => ptr,
+        None => {
+            vk.destroy_buffer(staging_buffer, staging_allocation)?;
+            bail!("staging buffer memory is not host visible");
+        }
+    };
+    unsafe {
+        std::ptr::copy_nonoverlapping(data.as_ptr(), mapped_ptr.as_ptr().cast::<T>(), data.len());
+    }
+
+    let (buffer, allocation) = match vk.allocate_buffer(
+        size,
+        usage | vk::BufferUsageFlags::TRANSFER_DST,
+        MemoryLocation::GpuOnly,
+    ) {
+        Ok(buffer) => buffer,
+        Err(err) => {
+            vk.destroy_buffer(staging_buffer, staging_allocation)?;
+            return Err(err);
+        }
+    };
+
+    let copy_result = vk.immediate_submit(|cmd| unsafe {
+        let region = vk::BufferCopy::builder().size(size).build();
+        vk.device()
+            .cmd_copy_buffer(cmd, staging_buffer, buffer, &[region]);
+    });
+    vk.destroy_buffer(staging_buffer, staging_allocation)?;
+
+    if let Err(err) = copy_result {
+        vk.destroy_buffer(buffer, allocation)?;
+        return Err(err);
+    }
+
+    Ok((buffer, allocation))
+}
+
+// blitting a level into the next needs the image to be both blit source and destination,
+// with linear filtering
+fn supports_mipmap_blits(vk: &Vk, format: Format) -> bool {
+    let format_properties = unsafe {
+        vk.instance()
+            .get_physical_device_format_properties(*vk.physical_device(), format)
+    };
+    format_properties.optimal_tiling_features.contains(
+        vk::FormatFeatureFlags::BLIT_SRC
+            | vk::FormatFeatureFlags::BLIT_DST
+            | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR,
+    )
+}
+
+// fills mip levels 1.. by repeatedly blitting the previous level at half size, blocks until done
+// every level must be in TRANSFER_DST_OPTIMAL with level 0 filled, they end up in
+// SHADER_READ_ONLY_OPTIMAL
+pub fn generate_mipmaps(
+    vk: &Vk,
+    image: vk::Image,
+    format: Format,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+) -> anyhow::Result<()> {
+    let max_mip_levels = 32 - width.max(height).max(1).leading_zeros();
+    if mip_levels == 0 || mip_levels > max_mip_levels {
+        bail!("{mip_levels} mip levels requested, a {width}x{height} image has at most {max_mip_levels}");
+    }
+    if !supports_mipmap_blits(vk, format) {
+        bail!("format {format:?} does not support linearly filtered blits, can't blit mipmaps");
+    }
+
+    let barrier = |level: u32,
+                   old_layout: vk::ImageLayout,
+                   new_layout: vk::ImageLayout,
+                   src_access_mask: vk::AccessFlags,
+                   dst_access_mask: vk::AccessFlags| {
+        vk::ImageMemoryBarrier::builder()
+            .image(image)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_access_mask(src_access_mask)
+            .dst_access_mask(dst_access_mask)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(vk::ImageAspectFlags::COLOR)
+                    .base_mip_level(level)
+                    .level_count(1)
+                    .layer_count(1)
+                    .build(),
+            )
+            .build()
+    };
+    let mip_extent = |level: u32| vk::Offset3D {
+        x: (width >> level).max(1) as i32,
+        y: (height >> level).max(1) as i32,
+        z: 1,
+    };
+    let subresource = |level: u32| {
+        vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(level)
+            .layer_count(1)
+            .build()
+    };
+
+    vk.immediate_submit(|cmd| unsafe {
+        let device = vk.device();
+        for level in 1..mip_levels {
+            let src_level = level - 1;
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier(
+                    src_level,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                    vk::AccessFlags::TRANSFER_READ,
+                )],
+            );
+
+            let blit = vk::ImageBlit::builder()
+                .src_subresource(subresource(src_level))
+                .src_offsets([vk::Offset3D::default(), mip_extent(src_level)])
+                .dst_subresource(subresource(level))
+                .dst_offsets([vk::Offset3D::default(), mip_extent(level)])
+                .build();
+            device.cmd_blit_image(
+                cmd,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[blit],
+                vk::Filter::LINEAR,
+            );
+
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier(
+                    src_level,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    vk::AccessFlags::TRANSFER_READ,
+                    vk::AccessFlags::SHADER_READ,
+                )],
+            );
+        }
+
+        // the last level was only ever written to
+        device.cmd_pipeline_barrier(
+            cmd,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::FRAGMENT_SHADER | vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[],
+            &[barrier(
+                mip_levels - 1,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags::TRANSFER_WRITE,
+                vk::AccessFlags::SHADER_READ,
+            )],
+        );
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SamplerPreset {
+    LinearRepeat,
+    LinearClamp,
+    NearestRepeat,
+    // linear depth comparison (LESS_OR_EQUAL) for sampler2DShadow, outside the map is lit
+    Shadow,
+    // maximum anisotropy the device supports, plain LinearRepeat without sampler_anisotropy
+    AnisotropicRepeat,
+}
+
+// presets sample every mip level, use `Vk::sampler` to share them instead of creating copies
+pub fn create_preset_sampler(vk: &Vk, preset: SamplerPreset) -> anyhow::Result<vk::Sampler> {
+    let (filter, address_mode) = match preset {
+        SamplerPreset::LinearRepeat | SamplerPreset::AnisotropicRepeat => {
+            (vk::Filter::LINEAR, vk::SamplerAddressMode::REPEAT)
+        }
+        SamplerPreset::LinearClamp => (vk::Filter::LINEAR, vk::SamplerAddressMode::CLAMP_TO_EDGE),
+        SamplerPreset::NearestRepeat => (vk::Filter::NEAREST, vk::SamplerAddressMode::REPEAT),
+        SamplerPreset::Shadow => (vk::Filter::LINEAR, vk::SamplerAddressMode::CLAMP_TO_BORDER),
+    };
+    let mipmap_mode = if filter == vk::Filter::NEAREST {
+        vk::SamplerMipmapMode::NEAREST
+    } else {
+        vk::SamplerMipmapMode::LINEAR
+    };
+    let anisotropy = (preset == SamplerPreset::AnisotropicRepeat
+        && vk.enabled_features().sampler_anisotropy == vk::TRUE)
+        .then(|| {
+            let properties = unsafe {
+                vk.instance()
+                    .get_physical_device_properties(*vk.physical_device())
+            };
+            properties.limits.max_sampler_anisotropy
+        });
+    let is_shadow = preset == SamplerPreset::Shadow;
+
+    let create_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(filter)
+        .min_filter(filter)
+        .mipmap_mode(mipmap_mode)
+        .address_mode_u(address_mode)
+        .address_mode_v(address_mode)
+        .address_mode_w(address_mode)
+        .anisotropy_enable(anisotropy.is_some())
+        .max_anisotropy(anisotropy.unwrap_or(1.0))
+        .compare_enable(is_shadow)
+        .compare_op(if is_shadow {
+            vk::CompareOp::LESS_OR_EQUAL
+        } else {
+            vk::CompareOp::ALWAYS
+        })
+        .border_color(vk::BorderColor::FLOAT_OPAQUE_WHITE)
+        .min_lod(0.0)
+        .max_lod(vk::LOD_CLAMP_NONE)
+        .build();
+    unsafe {
+        vk.device()
+            .create_sampler(&create_info, None)
+            .context("failed to create sampler")
+    }
+}
+
+// `anisotropy` is clamped to the device limit and ignored with a warning unless
+// sampler_anisotropy was enabled through `App::required_device_features`
+pub fn create_sampler(
+    vk: &Vk,
+    filter: vk::Filter,
+    address_mode: vk::SamplerAddressMode,
+    anisotropy: Option<f32>,
+    mip_levels: u32,
+) -> anyhow::Result<vk::Sampler> {
+    let anisotropy = anisotropy.and_then(|anisotropy| {
+        if vk.enabled_features().sampler_anisotropy != vk::TRUE {
+            log::warn!("sampler anisotropy requested but the feature is not enabled, ignoring");
+            return None;
+        }
+        let properties = unsafe {
+            vk.instance()
+                .get_physical_device_properties(*vk.physical_device())
+        };
+        Some(anisotropy.clamp(1.0, properties.limits.max_sampler_anisotropy))
+    });
+    let mipmap_mode = if filter == vk::Filter::NEAREST {
+        vk::SamplerMipmapMode::NEAREST
+    } else {
+        vk::SamplerMipmapMode::LINEAR
+    };
+
+    let create_info = vk::SamplerCreateInfo::builder()
+        .mag_filter(filter)
+        .min_filter(filter)
+        .mipmap_mode(mipmap_mode)
+        .address_mode_u(address_mode)
+        .address_mode_v(address_mode)
+        .address_mode_w(address_mode)
+        .anisotropy_enable(anisotropy.is_some())
+        .max_anisotropy(anisotropy.unwrap_or(1.0))
+        .min_lod(0.0)
+        .max_lod(mip_levels as f32)
+        .build();
+    unsafe {
+        vk.device()
+            .create_sampler(&create_info, None)
+            .context("failed to create sampler")
+    }
+}
+
+// sampled RGBA8 sRGB image with a full mip chain when the format supports linear blits
+pub struct Texture2D {
+    image: vk::Image,
+    allocation: Allocation,
+    view: vk::ImageView,
+    mip_levels: u32,
+    extent: vk::Extent2D,
+}
+
+impl Texture2D {
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn destroy(self, vk: &mut Vk) -> anyhow::Result<()> {
+        unsafe { vk.device().destroy_image_view(self.view, None) };
+        vk.destroy_image(self.image, self.allocation)
+    }
+}
+
+// decodes a png or jpeg file and uploads it to a device local image in
+// SHADER_READ_ONLY_OPTIMAL, blocks until the upload is complete
+pub fn load_texture_2d(vk: &Vk, path: &Path) -> anyhow::Result<Texture2D> {
+    let pixels = image::open(path)
+        .with_context(|| format!("failed to decode image {}", path.display()))?
+        .to_rgba8();
+    let (width, height) = pixels.dimensions();
+    create_texture_2d(vk, vk::Extent2D { width, height }, &pixels)
+        .with_context(|| format!("failed to create texture from {}", path.display()))
+}
+
+// `pixels` are tightly packed RGBA8 rows in sRGB, see `load_texture_2d`
+pub fn create_texture_2d(
+    vk: &Vk,
+    extent: vk::Extent2D,
+    pixels: &[u8],
+) -> anyhow::Result<Texture2D> {
+    check_image_extent(vk, extent)?;
+    let vk::Extent2D { width, height } = extent;
+    if pixels.len() as u64 != width as u64 * height as u64 * 4 {
+        bail!(
+            "{} bytes don't match a {width}x{height} RGBA8 image",
+            pixels.len()
+        );
+    }
+    let format = Format::R8G8B8A8_SRGB;
+
+    // without blit support the texture is left with a single level
+    let mip_levels = if supports_mipmap_blits(vk, format) {
+        32 - width.max(height).leading_zeros()
+    } else {
+        1
+    };
+
+    let (staging_buffer, staging_allocation) = vk.allocate_buffer(
+        pixels.len() as u64,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        MemoryLocation::CpuToGpu,
+    )?;
+    let Some(mapped_ptr) = staging_allocation.mapped_ptr() else {
+        vk.destroy_buffer(staging_buffer, staging_allocation)?;
+        bail!("staging buffer memory is not host visible");
+    };
+    unsafe {
+        std::ptr::copy_nonoverlapping(
+            pixels.as_ptr(),
+            mapped_ptr.as_ptr().cast::<u8>(),
+            pixels.len(),
+        );
+    }
+
+    let create_info = vk::ImageCreateInfo::builder()
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(format)
+        .extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        })
+        .mip_levels(mip_levels)
+        .array_layers(1)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+        .usage(
+            vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_DST
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+        )
+        .sharing_mode(vk::SharingMode::EXCLUSIVE)
+        .initial_layout(vk::ImageLayout::UNDEFINED)
+        .build();
+    let (image, allocation) = match vk.allocate_image(&create_info, MemoryLocation::GpuOnly) {
+        Ok(image) => image,
+        Err(err) => {
+            vk.destroy_buffer(staging_buffer, staging_allocation)?;
+            return Err(err);
+        }
+    };
+
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .level_count(mip_levels)
+        .layer_count(1)
+        .build();
+    let upload_result = vk
+        .immediate_submit(|cmd| unsafe {
+            let device = vk.device();
+            let to_transfer_dst = vk::ImageMemoryBarrier::builder()
+                .image(image)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .subresource_range(subresource_range)
+                .build();
+            device.cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer_dst],
+            );
+
+            let region = vk::BufferImageCopy::builder()
+                .image_subresource(
+                    vk::ImageSubresourceLayers::builder()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .layer_count(1)
+                        .build(),
+                )
+                .image_extent(create_info.extent)
+                .build();
+            device.cmd_copy_buffer_to_image(
+                cmd,
+                staging_buffer,
+                image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+            );
+
+            // with mipmaps the transition to SHADER_READ_ONLY_OPTIMAL is done by generate_mipmaps
+            if mip_levels == 1 {
+                let to_shader_read = vk::ImageMemoryBarrier::builder()
+                    .image(image)
+                    .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::SHADER_READ)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .subresource_range(subresource_range)
+                    .build();
+                device.cmd_pipeline_barrier(
+                    cmd,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &[to_shader_read],
+                );
+            }
+        })
+        .and_then(|_| {
+            if mip_levels > 1 {
+                generate_mipmaps(vk, image, format, width, height, mip_levels)
+            } else {
+                Ok(())
+            }
+        });
+    vk.destroy_buffer(staging_buffer, staging_allocation)?;
+    if let Err(err) = upload_result {
+        vk.destroy_image(image, allocation)?;
+        return Err(err);
+    }
+
+    let view_create_info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .format(format)
+        .subresource_range(subresource_range)
+        .build();
+    let view = match unsafe { vk.device().create_image_view(&view_create_info, None) } {
+        Ok(view) => view,
+        Err(err) => {
+            vk.destroy_image(image, allocation)?;
+            return Err(err).context("failed to create texture image view");
+        }
+    };
+
+    Ok(Texture2D {
+        image,
+        allocation,
+        view,
+        mip_levels,
+        extent,
+    })
+}
+
+// every texture is created through here, so oversized images fail with a readable error
+// instead of in the driver
+fn check_image_extent(vk: &Vk, extent: vk::Extent2D) -> anyhow::Result<()> {
+    let vk::Extent2D { width, height } = extent;
+    if width == 0 || height == 0 {
+        bail!("image is empty");
+    }
+    let max_dimension = vk.max_image_dimension_2d();
+    if width > max_dimension || height > max_dimension {
+        bail!(
+            "image is {width}x{height}, the device supports at most {max_dimension}x{max_dimension}, downscale it"
+        );
+    }
+    Ok(())
+}
+
+// records a single use command buffer, submits it to the main queue and waits for it to finish
+fn one_time_submit(
+    vk: &Vk,
+    cmd_pool: vk::CommandPool,
+    record: impl FnOnce(&Device, vk::CommandBuffer),
+) -> anyhow::Result<()> {
+    let device = vk.device();
+    let allocate_info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(cmd_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1)
+        .build();
+    let cmd = unsafe {
+        device
+            .allocate_command_buffers(&allocate_info)
+            .context("failed to allocate command buffer")?[0]
+    };
+
+    let result = (|| unsafe {
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .build();
+        device
+            .begin_command_buffer(cmd, &begin_info)
+            .context("failed to begin command buffer")?;
+        record(device, cmd);
+        device
+            .end_command_buffer(cmd)
+            .context("failed to end command buffer")?;
+
+        let fence = device
+            .create_fence(&vk::FenceCreateInfo::default(), None)
+            .context("failed to create fence")?;
+        let command_buffers = [cmd];
+        let submit_info = vk::SubmitInfo::builder()
+            .command_buffers(&command_buffers)
+            .build();
+        let result = device
+            .queue_submit(*vk.queue(), &[submit_info], fence)
+            .context("failed to submit command buffer")
+            .and_then(|_| {
+                device
+                    .wait_for_fences(&[fence], true, u64::MAX)
+                    .context("failed to wait for fence")
+            });
+        device.destroy_fence(fence, None);
+        result
+    })();
+
+    unsafe { device.free_command_buffers(cmd_pool, &[cmd]) };
+    result
+}
+
+// copies a color image back as tightly packed RGBA8 rows, other formats are blitted to
+// R8G8B8A8 first keeping the sRGB encoding, the image is expected in `layout` and
+// transitioned back to it afterwards, so `layout` can't be UNDEFINED
+pub fn readback_image(
+    vk: &mut Vk,
+    cmd_pool: vk::CommandPool,
+    image: vk::Image,
+    format: Format,
+    extent: vk::Extent2D,
+    layout: vk::ImageLayout,
+) -> anyhow::Result<Vec<u8>> {
+    let readback_format = if is_srgb_format(format) {
+        Format::R8G8B8A8_SRGB
+    } else {
+        Format::R8G8B8A8_UNORM
+    };
+    let image_extent = vk::Extent3D {
+        width: extent.width,
+        height: extent.height,
+        depth: 1,
+    };
+
+    let blit_image = if format == readback_format {
+        None
+    } else {
+        let format_properties = unsafe {
+            vk.instance()
+                .get_physical_device_format_properties(*vk.physical_device(), format)
+        };
+        if !format_properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::BLIT_SRC)
+        {
+            bail!("{format:?} can't be blitted, it can't be converted for readback");
+        }
+        let create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(readback_format)
+            .extent(image_extent)
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::TRANSFER_SRC)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+        Some(vk.allocate_image(&create_info, MemoryLocation::GpuOnly)?)
+    };
+
+    let size = extent.width as usize * extent.height as usize * 4;
+    let (buffer, buffer_allocation) = match vk.allocate_buffer(
+        size as u64,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        MemoryLocation::GpuToCpu,
+    ) {
+        Ok(buffer) => buffer,
+        Err(err) => {
+            if let Some((blit_image, allocation)) = blit_image {
+                vk.destroy_image(blit_image, allocation)?;
+            }
+            return Err(err);
+        }
+    };
+
+    let subresource_layers = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .layer_count(1)
+        .build();
+    let copy_src = blit_image
+        .as_ref()
+        .map_or(image, |(blit_image, _)| *blit_image);
+    let readback_result = one_time_submit(vk, cmd_pool, |device, cmd| unsafe {
+        let color = vk::ImageAspectFlags::COLOR;
+        image_barrier(
+            vk,
+            cmd,
+            image,
+            layout,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            color,
+        );
+        if let Some((blit_image, _)) = &blit_image {
+            image_barrier(
+                vk,
+                cmd,
+                *blit_image,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                color,
+            );
+            let offsets = [
+                vk::Offset3D::default(),
+                vk::Offset3D {
+                    x: extent.width as i32,
+                    y: extent.height as i32,
+                    z: 1,
+                },
+            ];
+            let region = vk::ImageBlit::builder()
+                .src_subresource(subresource_layers)
+                .src_offsets(offsets)
+                .dst_subresource(subresource_layers)
+                .dst_offsets(offsets)
+                .build();
+            device.cmd_blit_image(
+                cmd,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                *blit_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                &[region],
+                vk::Filter::NEAREST,
+            );
+            image_barrier(
+                vk,
+                cmd,
+                *blit_image,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                color,
+            );
+        }
+
+        let region = vk::BufferImageCopy::builder()
+            .image_subresource(subresource_layers)
+            .image_extent(image_extent)
+            .build();
+        device.cmd_copy_image_to_buffer(
+            cmd,
+            copy_src,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer,
+            &[region],
+        );
+        image_barrier(
+            vk,
+            cmd,
+            image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            layout,
+            color,
+        );
+
+        // the fence wait alone doesn't make the copy visible to the host
+        let to_host = vk::MemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ)
+            .build();
+        device.cmd_pipeline_barrier(
+            cmd,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[to_host],
+            &[],
+            &[],
+        );
+    })
+    .and_then(|_| {
+        let mapped_ptr = buffer_allocation
+            .mapped_ptr()
+            .context("readback buffer memory is not host visible")?;
+        Ok(unsafe { std::slice::from_raw_parts(mapped_ptr.as_ptr().cast::<u8>(), size) }.to_vec())
+    });
+    vk.destroy_buffer(buffer, buffer_allocation)?;
+    if let Some((blit_image, allocation)) = blit_image {
+        vk.destroy_image(blit_image, allocation)?;
+    }
+    readback_result
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for u16 {}
+    impl Sealed for u32 {}
+}
+
+// element types usable in an index buffer
+pub trait IndexType: sealed::Sealed {
+    fn index_type() -> vk::IndexType;
+}
+
+impl IndexType for u16 {
+    fn index_type() -> vk::IndexType {
+        vk::IndexType::UINT16
+    }
+}
+
+impl IndexType for u32 {
+    fn index_type() -> vk::IndexType {
+        vk::IndexType::UINT32
+    }
+}
+
+// semaphore with a monotonically increasing 64 bit counter, can be waited on and signaled
+// from both the cpu and queue submissions
+pub struct TimelineSemaphore {
+    device: Device,
+    semaphore: vk::Semaphore,
+}
+
+impl TimelineSemaphore {
+    pub fn new(vk: &Vk, initial_value: u64) -> anyhow::Result<Self> {
+        if !vk.supports_timeline_semaphores() {
+            bail!("timeline semaphores are not supported by the device");
+        }
+        let mut type_create_info = vk::SemaphoreTypeCreateInfo::builder()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(initial_value)
+            .build();
+        let create_info = vk::SemaphoreCreateInfo::builder()
+            .push_next(&mut type_create_info)
+            .build();
+        let semaphore = unsafe {
+            vk.device()
+                .create_semaphore(&create_info, None)
+                .context("failed to create timeline semaphore")?
+        };
+
+        Ok(Self {
+            device: vk.device().clone(),
+            semaphore,
+        })
+    }
+
+    // raw handle for the wait/signal arrays of a queue submission
+    pub fn semaphore(&self) -> vk::Semaphore {
+        self.semaphore
+    }
+
+    pub fn value(&self) -> anyhow::Result<u64> {
+        unsafe {
+            self.device
+                .get_semaphore_counter_value(self.semaphore)
+                .context("failed to get timeline semaphore value")
+        }
+    }
+
+    // `value` must be larger than the current value
+    pub fn signal_value_from_cpu(&self, value: u64) -> anyhow::Result<()> {
+        let signal_info = vk::SemaphoreSignalInfo::builder()
+            .semaphore(self.semaphore)
+            .value(value)
+            .build();
+        unsafe {
+            self.device
+                .signal_semaphore(&signal_info)
+                .context("failed to signal timeline semaphore")
+        }
+    }
+
+    // returns false if `timeout_ns` elapsed before the counter reached `value`
+    pub fn wait_from_cpu(&self, value: u64, timeout_ns: u64) -> anyhow::Result<bool> {
+        let semaphores = [self.semaphore];
+        let values = [value];
+        let wait_info = vk::SemaphoreWaitInfo::builder()
+            .semaphores(&semaphores)
+            .values(&values)
+            .build();
+        match unsafe { self.device.wait_semaphores(&wait_info, timeout_ns) } {
+            Ok(()) => Ok(true),
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(err) => Err(err).context("failed to wait for timeline semaphore"),
+        }
+    }
+
+    pub fn destroy(self) {
+        unsafe { self.device.destroy_semaphore(self.semaphore, None) };
+    }
+}
+
+// a buffer together with its memory, freed when dropped, which has to happen before the
+// `Vk` is dropped, see `Vk::upload_to_buffer` to fill it
+pub struct AllocatedBuffer {
+    device: Device,
+    allocator: Arc<Mutex<Allocator>>,
+    buffer: vk::Buffer,
+    allocation: Allocation,
+    size: u64,
+}
+
+impl AllocatedBuffer {
+    // GpuOnly buffers also get TRANSFER_DST usage so they can be filled through a staging buffer
+    pub fn new(
+        vk: &Vk,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> anyhow::Result<Self> {
+        let usage = if location == MemoryLocation::GpuOnly {
+            usage | vk::BufferUsageFlags::TRANSFER_DST
+        } else {
+            usage
+        };
+        let (buffer, allocation) = vk.allocate_buffer(size, usage, location)?;
+
+        Ok(Self {
+            device: vk.device().clone(),
+            allocator: vk.shared_allocator(),
+            buffer,
+            allocation,
+            size,
+        })
+    }
+
+    pub fn buffer(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    pub fn allocation(&self) -> &Allocation {
+        &self.allocation
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Drop for AllocatedBuffer {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_buffer(self.buffer, None) };
+        let allocation = std::mem::take(&mut self.allocation);
+        let result = self
+            .allocator
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .free(allocation);
+        if let Err(err) = result {
+            log::error!("failed to free buffer memory: {err}");
+        }
+    }
+}
+
+pub struct IndexBuffer {
+    device: Device,
+    buffer: AllocatedBuffer,
+    index_type: vk::IndexType,
+    index_count: u32,
+}
+
+impl IndexBuffer {
+    pub fn buffer(&self) -> vk::Buffer {
+        self.buffer.buffer()
+    }
+
+    pub fn index_type(&self) -> vk::IndexType {
+        self.index_type
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.index_count
+    }
+
+    pub fn bind(&self, cmd: vk::CommandBuffer) {
+        unsafe {
+            self.device
+                .cmd_bind_index_buffer(cmd, self.buffer.buffer(), 0, self.index_type)
+        }
+    }
+
+    // draws every index in the buffer, expects it to be bound
+    pub fn draw_indexed(&self, cmd: vk::CommandBuffer, instances: u32) {
+        unsafe {
+            self.device
+                .cmd_draw_indexed(cmd, self.index_count, instances, 0, 0, 0)
+        }
+    }
+}
+
+pub fn create_index_buffer<T: Copy + IndexType>(
+    vk: &Vk,
+    indices: &[T],
+) -> anyhow::Result<IndexBuffer> {
+    let buffer = vk.create_device_local_buffer(indices, vk::BufferUsageFlags::INDEX_BUFFER)?;
+
+    Ok(IndexBuffer {
+        device: vk.device().clone(),
+        buffer,
+        index_type: T::index_type(),
+        index_count: indices.len() as u32,
+    })
+}
+
+// `initial_data` is the output of `serialize_pipeline_cache` from a previous run, drivers
+// ignore data written by a different device or driver version
+pub fn create_pipeline_cache(
+    vk: &Vk,
+    initial_data: Option<&[u8]>,
+) -> anyhow::Result<vk::PipelineCache> {
+    let create_info = vk::PipelineCacheCreateInfo::builder()
+        .initial_data(initial_data.unwrap_or_default())
+        .build();
+    unsafe {
+        vk.device()
+            .create_pipeline_cache(&create_info, None)
+            .context("failed to create pipeline cache")
+    }
+}
+
+pub fn serialize_pipeline_cache(vk: &Vk, cache: vk::PipelineCache) -> anyhow::Result<Vec<u8>> {
+    unsafe {
+        vk.device()
+            .get_pipeline_cache_data(cache)
+            .context("failed to get pipeline cache data")
+    }
+}
+
+// highest sample count not above `requested` that color attachments, and depth attachments
+// of `depth_format` if given, support, falls back to TYPE_1
+pub fn clamp_sample_count(
+    vk: &Vk,
+    requested: vk::SampleCountFlags,
+    depth_format: Option<vk::Format>,
+) -> vk::SampleCountFlags {
+    let limits = unsafe {
+        vk.instance()
+            .get_physical_device_properties(*vk.physical_device())
+    }
+    .limits;
+    let mut supported = limits.framebuffer_color_sample_counts;
+    if let Some(format) = depth_format {
+        supported &= limits.framebuffer_depth_sample_counts;
+        if format_has_stencil(format) {
+            supported &= limits.framebuffer_stencil_sample_counts;
+        }
+    }
+
+    [
+        vk::SampleCountFlags::TYPE_64,
+        vk::SampleCountFlags::TYPE_32,
+        vk::SampleCountFlags::TYPE_16,
+        vk::SampleCountFlags::TYPE_8,
+        vk::SampleCountFlags::TYPE_4,
+        vk::SampleCountFlags::TYPE_2,
+    ]
+    .into_iter()
+    .find(|&e| e.as_raw() <= requested.as_raw() && supported.contains(e))
+    .unwrap_or(vk::SampleCountFlags::TYPE_1)
+}
+
+// 0 when the graphics queue doesn't support timestamps
+pub fn timestamp_valid_bits(vk: &Vk) -> u32 {
+    let queue_families = unsafe {
+        vk.instance()
+            .get_physical_device_queue_family_properties(*vk.physical_device())
+    };
+    queue_families
+        .get(vk.queue_family_idx() as usize)
+        .map_or(0, |e| e.timestamp_valid_bits)
+}
+
+pub fn create_timestamp_pool(vk: &Vk, count: u32) -> anyhow::Result<vk::QueryPool> {
+    let create_info = vk::QueryPoolCreateInfo::builder()
+        .query_type(vk::QueryType::TIMESTAMP)
+        .query_count(count)
+        .build();
+    unsafe {
+        vk.device()
+            .create_query_pool(&create_info, None)
+            .context("failed to create timestamp query pool")
+    }
+}
+
+// queries have to be reset before they are written again, outside of a rendering scope
+pub fn reset_timestamp_pool(
+    vk: &Vk,
+    cmd: vk::CommandBuffer,
+    pool: vk::QueryPool,
+    first: u32,
+    count: u32,
+) {
+    unsafe { vk.device().cmd_reset_query_pool(cmd, pool, first, count) };
+}
+
+// the timestamp is taken once all previous commands reached `stage`
+pub fn write_timestamp(
+    vk: &Vk,
+    cmd: vk::CommandBuffer,
+    pool: vk::QueryPool,
+    index: u32,
+    stage: vk::PipelineStageFlags,
+) {
+    unsafe { vk.device().cmd_write_timestamp(cmd, stage, pool, index) };
+}
+
+// milliseconds since an arbitrary device specific point, only differences are meaningful
+// doesn't wait, fails with NOT_READY until the command buffer writing them has completed
+pub fn read_timestamps(
+    vk: &Vk,
+    pool: vk::QueryPool,
+    first: u32,
+    count: u32,
+) -> anyhow::Result<Vec<f64>> {
+    let valid_bits = timestamp_valid_bits(vk);
+    if valid_bits == 0 {
+        bail!("graphics queue doesn't support timestamps");
+    }
+    let mask = u64::MAX >> (64 - valid_bits);
+    let timestamp_period = unsafe {
+        vk.instance()
+            .get_physical_device_properties(*vk.physical_device())
+    }
+    .limits
+    .timestamp_period as f64;
+
+    let mut ticks = vec![0u64; count as usize];
+    unsafe {
+        vk.device()
+            .get_query_pool_results(
+                pool,
+                first,
+                count,
+                &mut ticks,
+                vk::QueryResultFlags::TYPE_64,
+            )
+            .context("failed to read timestamps")?
+    };
+
+    Ok(ticks
+        .into_iter()
+        .map(|e| (e & mask) as f64 * timestamp_period / 1_000_000.0)
+        .collect())
+}
+
+pub fn create_occlusion_pool(vk: &Vk, count: u32) -> anyhow::Result<vk::QueryPool> {
+    let create_info = vk::QueryPoolCreateInfo::builder()
+        .query_type(vk::QueryType::OCCLUSION)
+        .query_count(count)
+        .build();
+    unsafe {
+        vk.device()
+            .create_query_pool(&create_info, None)
+            .context("failed to create occlusion query pool")
+    }
+}
+
+// queries have to be reset before they are begun again, outside of a rendering scope
+pub fn reset_occlusion_pool(
+    vk: &Vk,
+    cmd: vk::CommandBuffer,
+    pool: vk::QueryPool,
+    first: u32,
+    count: u32,
+) {
+    unsafe { vk.device().cmd_reset_query_pool(cmd, pool, first, count) };
+}
+
+// without `precise` the result is only guaranteed to be non zero when any sample passed,
+// `precise` counts the samples and needs the occlusionQueryPrecise feature
+pub fn begin_occlusion_query(
+    vk: &Vk,
+    cmd: vk::CommandBuffer,
+    pool: vk::QueryPool,
+    query: u32,
+    precise: bool,
+) {
+    let flags = if precise {
+        vk::QueryControlFlags::PRECISE
+    } else {
+        vk::QueryControlFlags::empty()
+    };
+    unsafe { vk.device().cmd_begin_query(cmd, pool, query, flags) };
+}
+
+pub fn end_occlusion_query(vk: &Vk, cmd: vk::CommandBuffer, pool: vk::QueryPool, query: u32) {
+    unsafe { vk.device().cmd_end_query(cmd, pool, query) };
+}
+
+// blocks until the queries are available, they must have been ended in a submitted
+// command buffer or this never returns
+pub fn read_occlusion_results(
+    vk: &Vk,
+    pool: vk::QueryPool,
+    first: u32,
+    count: u32,
+) -> anyhow::Result<Vec<u64>> {
+    let mut results = vec![0u64; count as usize];
+    unsafe {
+        vk.device()
+            .get_query_pool_results(
+                pool,
+                first,
+                count,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            )
+            .context("failed to read occlusion query results")?
+    };
+
+    Ok(results)
+}
+
+// counters of a single pipeline statistics query, statistics of disabled shader stages
+// are always 0
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PipelineStats {
+    pub input_assembly_vertices: u64,
+    pub input_assembly_primitives: u64,
+    pub vertex_shader_invocations: u64,
+    pub geometry_shader_invocations: u64,
+    pub geometry_shader_primitives: u64,
+    pub clipping_invocations: u64,
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+    pub tessellation_control_shader_patches: u64,
+    pub tessellation_evaluation_shader_invocations: u64,
+    pub compute_shader_invocations: u64,
+}
+
+// geometry and tessellation statistics are only counted when those stages are enabled
+fn pipeline_statistic_flags(vk: &Vk) -> vk::QueryPipelineStatisticFlags {
+    let features = vk.enabled_features();
+    let mut flags = vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES
+        | vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES
+        | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS
+        | vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS
+        | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES
+        | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS
+        | vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS;
+    if features.geometry_shader == vk::TRUE {
+        flags |= vk::QueryPipelineStatisticFlags::GEOMETRY_SHADER_INVOCATIONS
+            | vk::QueryPipelineStatisticFlags::GEOMETRY_SHADER_PRIMITIVES;
+    }
+    if features.tessellation_shader == vk::TRUE {
+        flags |= vk::QueryPipelineStatisticFlags::TESSELLATION_CONTROL_SHADER_PATCHES
+            | vk::QueryPipelineStatisticFlags::TESSELLATION_EVALUATION_SHADER_INVOCATIONS;
+    }
+    flags
+}
+
+// a pool with a single query, needs `pipeline_statistics_query` in
+// `App::required_device_features`
+pub fn create_pipeline_stats_pool(vk: &Vk) -> anyhow::Result<vk::QueryPool> {
+    if vk.enabled_features().pipeline_statistics_query != vk::TRUE {
+        bail!("the pipeline_statistics_query feature is not enabled");
+    }
+    let create_info = vk::QueryPoolCreateInfo::builder()
+        .query_type(vk::QueryType::PIPELINE_STATISTICS)
+        .query_count(1)
+        .pipeline_statistics(pipeline_statistic_flags(vk))
+        .build();
+    unsafe {
+        vk.device()
+            .create_query_pool(&create_info, None)
+            .context("failed to create pipeline statistics query pool")
+    }
+}
+
+// resets the query too, so this has to be recorded outside of a rendering scope
+pub fn begin_pipeline_stats(vk: &Vk, cmd: vk::CommandBuffer, pool: vk::QueryPool) {
+    unsafe {
+        vk.device().cmd_reset_query_pool(cmd, pool, 0, 1);
+        vk.device()
+            .cmd_begin_query(cmd, pool, 0, vk::QueryControlFlags::empty());
+    }
+}
+
+pub fn end_pipeline_stats(vk: &Vk, cmd: vk::CommandBuffer, pool: vk::QueryPool) {
+    unsafe { vk.device().cmd_end_query(cmd, pool, 0) };
+}
+
+// blocks until the query is available, it must have been ended in a submitted command buffer
+pub fn read_pipeline_stats(vk: &Vk, pool: vk::QueryPool) -> anyhow::Result<PipelineStats> {
+    let flags = pipeline_statistic_flags(vk);
+    // one counter per enabled flag, in bit order
+    let mut counters = vec![0u64; flags.as_raw().count_ones() as usize];
+    unsafe {
+        vk.device()
+            .get_query_pool_results(
+                pool,
+                0,
+                1,
+                &mut counters,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            )
+            .context("failed to read pipeline statistics")?
+    };
+
+    let mut counters = counters.into_iter();
+    let mut next = |flag: vk::QueryPipelineStatisticFlags| {
+        if flags.contains(flag) {
+            counters.next().unwrap_or_default()
+        } else {
+            0
+        }
+    };
+    // declaration order has to follow the bit order of the flags
+    Ok(PipelineStats {
+        input_assembly_vertices: next(vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES),
+        input_assembly_primitives: next(vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES),
+        vertex_shader_invocations: next(vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS),
+        geometry_shader_invocations: next(
+            vk::QueryPipelineStatisticFlags::GEOMETRY_SHADER_INVOCATIONS,
+        ),
+        geometry_shader_primitives: next(
+            vk::QueryPipelineStatisticFlags::GEOMETRY_SHADER_PRIMITIVES,
+        ),
+        clipping_invocations: next(vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS),
+        clipping_primitives: next(vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES),
+        fragment_shader_invocations: next(
+            vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS,
+        ),
+        tessellation_control_shader_patches: next(
+            vk::QueryPipelineStatisticFlags::TESSELLATION_CONTROL_SHADER_PATCHES,
+        ),
+        tessellation_evaluation_shader_invocations: next(
+            vk::QueryPipelineStatisticFlags::TESSELLATION_EVALUATION_SHADER_INVOCATIONS,
+        ),
+        compute_shader_invocations: next(
+            vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS,
+        ),
+    })
+}
+
+pub fn create_pipeline_layout(
+    vk: &Vk,
+    set_layouts: &[vk::DescriptorSetLayout],
+    push_constant_ranges: &[vk::PushConstantRange],
+) -> anyhow::Result<vk::PipelineLayout> {
+    let create_info = vk::PipelineLayoutCreateInfo::builder()
+        .set_layouts(set_layouts)
+        .push_constant_ranges(push_constant_ranges)
+        .build();
+    let layout = unsafe {
+        vk.device()
+            .create_pipeline_layout(&create_info, None)
+            .context("failed to create pipeline layout")?
+    };
+    vk.register_push_constant_ranges(layout, push_constant_ranges);
+    Ok(layout)
+}
+
+// for shaders that use no descriptors or push constants
+pub fn create_empty_pipeline_layout(vk: &Vk) -> anyhow::Result<vk::PipelineLayout> {
+    create_pipeline_layout(vk, &[], &[])
+}
+
+// the shader module only lives for the duration of the call
+pub fn create_compute_pipeline(
+    vk: &Vk,
+    spirv: &[u32],
+    entry: &CStr,
+    layout: vk::PipelineLayout,
+) -> anyhow::Result<vk::Pipeline> {
+    create_compute_pipeline_impl(vk, spirv, entry, layout, None)
+}
+
+// `subgroup_size` must be a power of two within `SubgroupProperties::size_control`
+pub fn create_compute_pipeline_with_subgroup_size(
+    vk: &Vk,
+    spirv: &[u32],
+    entry: &CStr,
+    layout: vk::PipelineLayout,
+    subgroup_size: u32,
+) -> anyhow::Result<vk::Pipeline> {
+    let Some((min, max)) = vk.subgroup_properties().size_control else {
+        bail!("subgroup size control is not supported for compute shaders");
+    };
+    if !subgroup_size.is_power_of_two() || subgroup_size < min || subgroup_size > max {
+        bail!("subgroup size {subgroup_size} is not a power of two in [{min}, {max}]");
+    }
+    create_compute_pipeline_impl(vk, spirv, entry, layout, Some(subgroup_size))
+}
+
+fn create_compute_pipeline_impl(
+    vk: &Vk,
+    spirv: &[u32],
+    entry: &CStr,
+    layout: vk::PipelineLayout,
+    subgroup_size: Option<u32>,
+) -> anyhow::Result<vk::Pipeline> {
+    let device = vk.device();
+    let module = load_shader_module(vk, spirv)?;
+
+    let mut required_subgroup_size =
+        vk::PipelineShaderStageRequiredSubgroupSizeCreateInfoEXT::builder()
+            .required_subgroup_size(subgroup_size.unwrap_or_default())
+            .build();
+    let mut stage = vk::PipelineShaderStageCreateInfo::builder()
+        .stage(vk::ShaderStageFlags::COMPUTE)
+        .module(module)
+        .name(entry);
+    if subgroup_size.is_some() {
+        stage = stage.push_next(&mut required_subgroup_size);
+    }
+    let stage = stage.build();
+    let create_info = vk::ComputePipelineCreateInfo::builder()
+        .stage(stage)
+        .layout(layout)
+        .build();
+    let pipelines =
+        unsafe { device.create_compute_pipelines(vk.pipeline_cache(), &[create_info], None) };
+    unsafe { device.destroy_shader_module(module, None) };
+
+    match pipelines {
+        Ok(pipelines) => Ok(pipelines[0]),
+        Err((_, err)) => Err(err).context("failed to create compute pipeline"),
+    }
+}
+
+// records vkCmdDispatch, the counts are in workgroups not invocations
+pub fn dispatch(vk: &Vk, cmd: vk::CommandBuffer, x: u32, y: u32, z: u32) {
+    unsafe { vk.device().cmd_dispatch(cmd, x, y, z) }
+}
+
+// stage and access a layout is used with, None for layouts without an obvious usage
+fn layout_stage_access(
+    layout: vk::ImageLayout,
+) -> Option<(vk::PipelineStageFlags2, vk::AccessFlags2)> {
+    use vk::{AccessFlags2 as A, ImageLayout as L, PipelineStageFlags2 as S};
+    let stage_access = match layout {
+        L::UNDEFINED | L::PREINITIALIZED => (S::TOP_OF_PIPE, A::NONE),
+        L::TRANSFER_SRC_OPTIMAL => (S::TRANSFER, A::TRANSFER_READ),
+        L::TRANSFER_DST_OPTIMAL => (S::TRANSFER, A::TRANSFER_WRITE),
+        L::COLOR_ATTACHMENT_OPTIMAL => (
+            S::COLOR_ATTACHMENT_OUTPUT,
+            A::COLOR_ATTACHMENT_READ | A::COLOR_ATTACHMENT_WRITE,
+        ),
+        L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL | L::DEPTH_ATTACHMENT_OPTIMAL => (
+            S::EARLY_FRAGMENT_TESTS | S::LATE_FRAGMENT_TESTS,
+            A::DEPTH_STENCIL_ATTACHMENT_READ | A::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        ),
+        L::DEPTH_STENCIL_READ_ONLY_OPTIMAL | L::DEPTH_READ_ONLY_OPTIMAL => (
+            S::EARLY_FRAGMENT_TESTS | S::LATE_FRAGMENT_TESTS | S::FRAGMENT_SHADER,
+            A::DEPTH_STENCIL_ATTACHMENT_READ | A::SHADER_READ,
+        ),
+        L::SHADER_READ_ONLY_OPTIMAL => (S::FRAGMENT_SHADER | S::COMPUTE_SHADER, A::SHADER_READ),
+        L::GENERAL => (S::ALL_COMMANDS, A::MEMORY_READ | A::MEMORY_WRITE),
+        // presentation happens outside the pipeline, the semaphores handle the dependency
+        L::PRESENT_SRC_KHR => (S::BOTTOM_OF_PIPE, A::NONE),
+        _ => return None,
+    };
+    Some(stage_access)
+}
+
+// transitions the whole image, stage and access masks are deduced from the layouts
+// unknown layouts use a full ALL_COMMANDS barrier
+pub fn image_barrier(
+    vk: &Vk,
+    cmd: vk::CommandBuffer,
+    image: vk::Image,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    aspect: vk::ImageAspectFlags,
+) {
+    let full_barrier = (
+        vk::PipelineStageFlags2::ALL_COMMANDS,
+        vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+    );
+    let ((src_stage, src_access), (dst_stage, dst_access)) = match (old_layout, new_layout) {
+        // freshly acquired swapchain image, waiting on COLOR_ATTACHMENT_OUTPUT chains the
+        // transition after the acquire semaphore wait, which is usually done at that stage
+        (vk::ImageLayout::UNDEFINED, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL) => (
+            (
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags2::NONE,
+            ),
+            (
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags2::COLOR_ATTACHMENT_READ | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            ),
+        ),
+        // the present semaphore signaled after the submit makes the writes available
+        (vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageLayout::PRESENT_SRC_KHR) => (
+            (
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            ),
+            (
+                vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+                vk::AccessFlags2::NONE,
+            ),
+        ),
+        _ => match (
+            layout_stage_access(old_layout),
+            layout_stage_access(new_layout),
+        ) {
+            (Some(src), Some(dst)) => (src, dst),
+            _ => (full_barrier, full_barrier),
+        },
+    };
+    let subresource_range = vk::ImageSubresourceRange::builder()
+        .aspect_mask(aspect)
+        .level_count(vk::REMAINING_MIP_LEVELS)
+        .layer_count(vk::REMAINING_ARRAY_LAYERS)
+        .build();
+
+    let Some(khr_synchronization2) = vk.khr_synchronization2() else {
+        // the masks used above have the same bits in the legacy flags
+        let barrier = vk::ImageMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::from_raw(src_access.as_raw() as u32))
+            .dst_access_mask(vk::AccessFlags::from_raw(dst_access.as_raw() as u32))
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(subresource_range)
+            .build();
+        unsafe {
+            vk.device().cmd_pipeline_barrier(
+                cmd,
+                vk::PipelineStageFlags::from_raw(src_stage.as_raw() as u32),
+                vk::PipelineStageFlags::from_raw(dst_stage.as_raw() as u32),
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            )
+        };
+        return;
+    };
+
+    let barrier = vk::ImageMemoryBarrier2::builder()
+        .src_stage_mask(src_stage)
+        .src_access_mask(src_access)
+        .dst_stage_mask(dst_stage)
+        .dst_access_mask(dst_access)
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource_range)
+        .build();
+    let barriers = [barrier];
+    let dependency_info = vk::DependencyInfo::builder()
+        .image_memory_barriers(&barriers)
+        .build();
+    unsafe { khr_synchronization2.cmd_pipeline_barrier2(cmd, &dependency_info) };
+}
+
+// 128 bytes is the minimum maxPushConstantsSize every implementation guarantees
+const MIN_MAX_PUSH_CONSTANTS_SIZE: usize = 128;
+
+pub fn push_constants<T: Copy>(
+    vk: &Vk,
+    cmd: vk::CommandBuffer,
+    layout: vk::PipelineLayout,
+    stages: vk::ShaderStageFlags,
+    offset: u32,
+    data: &T,
+) {
+    const {
+        assert!(
+            std::mem::size_of::<T>() <= MIN_MAX_PUSH_CONSTANTS_SIZE,
+            "push constant block is larger than the guaranteed 128 bytes"
+        )
+    };
+    let bytes = unsafe {
+        std::slice::from_raw_parts((data as *const T).cast::<u8>(), std::mem::size_of::<T>())
+    };
+    unsafe {
+        vk.device()
+            .cmd_push_constants(cmd, layout, stages, offset, bytes)
+    }
+}
+
+const SPIRV_MAGIC: u32 = 0x07230203;
+
+pub fn load_shader_module(vk: &Vk, spirv: &[u32]) -> anyhow::Result<vk::ShaderModule> {
+    match spirv.first() {
+        Some(&SPIRV_MAGIC) => {}
+        Some(magic) => {
+            bail!("invalid SPIR-V magic number {magic:#010x}, expected {SPIRV_MAGIC:#010x}")
+        }
+        None => bail!("SPIR-V code is empty"),
+    }
+
+    let create_info = vk::ShaderModuleCreateInfo::builder().code(spirv).build();
+    unsafe {
+        vk.device()
+            .create_shader_module(&create_info, None)
+            .context("failed to create shader module")
+    }
+}
+
+// `bytes` doesn't need to be 4 byte aligned, e.g. the output of `include_bytes!`
+pub fn load_shader_module_from_bytes(vk: &Vk, bytes: &[u8]) -> anyhow::Result<vk::ShaderModule> {
+    if !bytes.len().is_multiple_of(4) {
+        bail!(
+            "SPIR-V is {} bytes, it must be a multiple of 4",
+            bytes.len()
+        );
+    }
+
+    let spirv: Vec<u32> = bytes
+        .chunks_exact(4)
+        .map(|e| u32::from_ne_bytes([e[0], e[1], e[2], e[3]]))
+        .collect();
+    load_shader_module(vk, &spirv)
+}
+
+pub fn load_shader_module_from_file(vk: &Vk, path: &Path) -> anyhow::Result<vk::ShaderModule> {
+    let bytes = std::fs::read(path)
+        .with_context(|| format!("failed to read shader file {}", path.display()))?;
+    load_shader_module_from_bytes(vk, &bytes)
+        .with_context(|| format!("failed to load shader {}", path.display()))
+}
+
+// 2d color image with a default view, optionally viewable through other compatible formats
+// (e.g. render into a UNORM view, sample through an SRGB view)
+pub struct Texture {
+    image: vk::Image,
+    allocation: Allocation,
+    format: Format,
+    extent: vk::Extent2D,
+    // formats `create_view_as` accepts, always includes `format`
+    view_formats: Vec<Format>,
+    view: vk::ImageView,
+}
+
+impl Texture {
+    // `view_formats` lists the additional formats the image may be viewed as, the image is
+    // created MUTABLE_FORMAT when it is not empty, with the formats listed through
+    // VK_KHR_image_format_list when the device supports it
+    pub fn new(
+        vk: &mut Vk,
+        extent: vk::Extent2D,
+        format: Format,
+        usage: vk::ImageUsageFlags,
+        view_formats: &[Format],
+    ) -> anyhow::Result<Self> {
+        check_image_extent(vk, extent)?;
+        let mut all_view_formats = vec![format];
+        all_view_formats.extend(view_formats.iter().filter(|e| **e != format));
+
+        let mut format_list = vk::ImageFormatListCreateInfo::builder()
+            .view_formats(&all_view_formats)
+            .build();
+        let mut create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        if all_view_formats.len() > 1 {
+            create_info = create_info.flags(vk::ImageCreateFlags::MUTABLE_FORMAT);
+            // the list only lets drivers keep optimizations MUTABLE_FORMAT would disable
+            if vk.supports_image_format_list() {
+                create_info = create_info.push_next(&mut format_list);
+            }
+        }
+        let (image, allocation) =
+            vk.allocate_image(&create_info.build(), MemoryLocation::GpuOnly)?;
+
+        let view = match create_color_image_view(vk, image, format) {
+            Ok(view) => view,
+            Err(err) => {
+                vk.destroy_image(image, allocation)?;
+                return Err(err);
+            }
+        };
+
+        Ok(Self {
+            image,
+            allocation,
+            format,
+            extent,
+            view_formats: all_view_formats,
+            view,
+        })
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    // the returned view aliases the same memory and is owned by the caller
+    pub fn create_view_as(&self, vk: &Vk, format: Format) -> anyhow::Result<vk::ImageView> {
+        if !self.view_formats.contains(&format) {
+            bail!(
+                "texture was not created with view format {format:?}, available: {:?}",
+                self.view_formats
+            );
+        }
+
+        create_color_image_view(vk, self.image, format)
+    }
+
+    pub fn destroy(self, vk: &mut Vk) -> anyhow::Result<()> {
+        unsafe { vk.device().destroy_image_view(self.view, None) };
+        vk.destroy_image(self.image, self.allocation)
+    }
+}
+
+fn create_color_image_view(
+    vk: &Vk,
+    image: vk::Image,
+    format: Format,
+) -> anyhow::Result<vk::ImageView> {
+    let create_info = vk::ImageViewCreateInfo::builder()
+        .image(image)
+        .view_type(vk::ImageViewType::TYPE_2D)
+        .format(format)
+        .subresource_range(
+            vk::ImageSubresourceRange::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .level_count(1)
+                .layer_count(1)
+                .build(),
+        )
+        .build();
+    unsafe {
+        vk.device()
+            .create_image_view(&create_info, None)
+            .context("failed to create image view")
+    }
+}
+
+// one sub-range per frame in flight in a single persistently mapped buffer, a frame's range
+// may only be written once that frame's latency fence has been waited on
+// `AppContext::create_uniform_buffer` sizes it for `App::max_frame_latency`
+pub struct UniformBuffer<T: Copy> {
+    buffer: AllocatedBuffer,
+    // size of T rounded up to minUniformBufferOffsetAlignment
+    stride: vk::DeviceSize,
+    frames_in_flight: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> UniformBuffer<T> {
+    pub fn new(vk: &mut Vk, frames_in_flight: usize) -> anyhow::Result<Self> {
+        let properties = unsafe {
+            vk.instance()
+                .get_physical_device_properties(*vk.physical_device())
+        };
+        let alignment = properties.limits.min_uniform_buffer_offset_alignment.max(1);
+        let stride = (std::mem::size_of::<T>() as vk::DeviceSize).next_multiple_of(alignment);
+        let buffer = AllocatedBuffer::new(
+            vk,
+            stride * frames_in_flight as vk::DeviceSize,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            MemoryLocation::CpuToGpu,
+        )?;
+        if buffer.allocation().mapped_ptr().is_none() {
+            bail!("uniform buffer memory is not host visible");
+        }
+
+        Ok(Self {
+            buffer,
+            stride,
+            frames_in_flight,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn buffer(&self) -> vk::Buffer {
+        self.buffer.buffer()
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames_in_flight
+    }
+
+    // `frame_index` is usually `AppContext::frame_slot`
+    pub fn update(&mut self, frame_index: usize, data: &T) {
+        assert!(
+            frame_index < self.frames_in_flight,
+            "frame index {frame_index} out of range"
+        );
+        // checked to be mapped on creation
+        let mapped_ptr = self.buffer.allocation().mapped_ptr().unwrap();
+        // the offset is only aligned for the device, not necessarily for `T`
+        unsafe {
+            mapped_ptr
+                .as_ptr()
+                .cast::<u8>()
+                .add(self.offset(frame_index) as usize)
+                .cast::<T>()
+                .write_unaligned(*data);
+        }
+    }
+
+    pub fn descriptor_buffer_info(&self, frame_index: usize) -> vk::DescriptorBufferInfo {
+        assert!(
+            frame_index < self.frames_in_flight,
+            "frame index {frame_index} out of range"
+        );
+        vk::DescriptorBufferInfo {
+            buffer: self.buffer.buffer(),
+            offset: self.offset(frame_index),
+            range: std::mem::size_of::<T>() as vk::DeviceSize,
+        }
+    }
+
+    fn offset(&self, frame_index: usize) -> vk::DeviceSize {
+        self.stride * frame_index as vk::DeviceSize
+    }
+}
+
+enum AttachmentMemory {
+    Allocator(Allocation),
+    // LAZILY_ALLOCATED memory, allocated outside gpu-allocator which can't request it
+    Lazy(vk::DeviceMemory),
+}
+
+// color/depth attachment, on tiled gpus transient attachments can live entirely in tile memory
+pub struct AttachmentImage {
+    image: vk::Image,
+    memory: AttachmentMemory,
+    view: vk::ImageView,
+    format: Format,
+    extent: vk::Extent2D,
+}
+
+impl AttachmentImage {
+    // with `transient` the image gets TRANSIENT_ATTACHMENT usage and is backed by lazily
+    // allocated memory when the device has it, falling back to regular device local memory
+    // with more than one layer the view is a 2D array, e.g. a layer per multiview view
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        vk: &mut Vk,
+        extent: vk::Extent2D,
+        format: Format,
+        usage: vk::ImageUsageFlags,
+        samples: vk::SampleCountFlags,
+        aspect_mask: vk::ImageAspectFlags,
+        layers: u32,
+        transient: bool,
+    ) -> anyhow::Result<Self> {
+        let usage = if transient {
+            usage | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT
+        } else {
+            usage
+        };
+        let create_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(layers)
+            .samples(samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .build();
+
+        let lazy = if transient {
+            create_lazily_allocated_image(vk, &create_info)?
+        } else {
+            None
+        };
+        let (image, memory) = match lazy {
+            Some((image, memory)) => (image, AttachmentMemory::Lazy(memory)),
+            None => {
+                let (image, allocation) =
+                    vk.allocate_image(&create_info, MemoryLocation::GpuOnly)?;
+                (image, AttachmentMemory::Allocator(allocation))
+            }
+        };
+
+        let view_create_info = vk::ImageViewCreateInfo::builder()
+            .image(image)
+            .view_type(if layers > 1 {
+                vk::ImageViewType::TYPE_2D_ARRAY
+            } else {
+                vk::ImageViewType::TYPE_2D
+            })
+            .format(format)
+            .subresource_range(
+                vk::ImageSubresourceRange::builder()
+                    .aspect_mask(aspect_mask)
+                    .level_count(1)
+                    .layer_count(layers)
+                    .build(),
+            )
+            .build();
+        let view = unsafe { vk.device().create_image_view(&view_create_info, None) };
+
+        let mut attachment = Self {
+            image,
+            memory,
+            view: vk::ImageView::null(),
+            format,
+            extent,
+        };
+        match view {
+            Ok(view) => {
+                attachment.view = view;
+                Ok(attachment)
+            }
+            Err(err) => {
+                attachment.destroy(vk)?;
+                Err(err).context("failed to create attachment image view")
+            }
+        }
+    }
+
+    pub fn image(&self) -> vk::Image {
+        self.image
+    }
+
+    pub fn view(&self) -> vk::ImageView {
+        self.view
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn is_lazily_allocated(&self) -> bool {
+        matches!(self.memory, AttachmentMemory::Lazy(_))
+    }
+
+    pub fn destroy(self, vk: &mut Vk) -> anyhow::Result<()> {
+        unsafe { vk.device().destroy_image_view(self.view, None) };
+        match self.memory {
+            AttachmentMemory::Allocator(allocation) => vk.destroy_image(self.image, allocation),
+            AttachmentMemory::Lazy(memory) => {
+                unsafe {
+                    vk.device().destroy_image(self.image, None);
+                    vk.device().free_memory(memory, None);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+// returns None if the device has no lazily allocated memory type usable by the image
+fn create_lazily_allocated_image(
+    vk: &Vk,
+    create_info: &vk::ImageCreateInfo,
+) -> anyhow::Result<Option<(vk::Image, vk::DeviceMemory)>> {
+    let device = vk.device();
+    let image = unsafe {
+        device
+            .create_image(create_info, None)
+            .context("failed to create image")?
+    };
+    let requirements = unsafe { device.get_image_memory_requirements(image) };
+    let memory_properties = unsafe {
+        vk.instance()
+            .get_physical_device_memory_properties(*vk.physical_device())
+    };
+
+    let memory_type_idx = (0..memory_properties.memory_type_count).find(|idx| {
+        let flags = memory_properties.memory_types[*idx as usize].property_flags;
+        requirements.memory_type_bits & (1 << idx) != 0
+            && flags.contains(vk::MemoryPropertyFlags::LAZILY_ALLOCATED)
+    });
+    let Some(memory_type_idx) = memory_type_idx else {
+        unsafe { device.destroy_image(image, None) };
+        return Ok(None);
+    };
+
+    let allocate_info = vk::MemoryAllocateInfo::builder()
+        .allocation_size(requirements.size)
+        .memory_type_index(memory_type_idx)
+        .build();
+    let memory = match unsafe { device.allocate_memory(&allocate_info, None) } {
+        Ok(memory) => memory,
+        Err(err) => {
+            log::warn!("failed to allocate lazily allocated memory, falling back: {err}");
+            unsafe { device.destroy_image(image, None) };
+            return Ok(None);
+        }
+    };
+    if let Err(err) = unsafe { device.bind_image_memory(image, memory, 0) } {
+        unsafe {
+            device.destroy_image(image, None);
+            device.free_memory(memory, None);
+        }
+        return Err(err).context("failed to bind lazily allocated memory");
+    }
+
+    Ok(Some((image, memory)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue_family(queue_flags: vk::QueueFlags) -> vk::QueueFamilyProperties {
+        vk::QueueFamilyProperties {
+            queue_flags,
+            queue_count: 1,
+            ..Default::default()
+        }
+    }
+
+    fn device_info(
+        raw: u64,
+        device_type: PhysicalDeviceType,
+        max_image_dimension: u32,
+    ) -> PhysicalDeviceInfo {
+        let mut properties = vk::PhysicalDeviceProperties {
+            device_type,
+            ..Default::default()
+        };
+        properties.limits.max_image_dimension2_d = max_image_dimension;
+        PhysicalDeviceInfo {
+            physical_device: <PhysicalDevice as vk::Handle>::from_raw(raw),
+            properties,
+            features: PhysicalDeviceFeatures::default(),
+            name: format!("device {raw}"),
+        }
+    }
+
+    #[test]
+    fn devices_with_equal_scores_are_both_candidates() {
+        let integrated = device_info(1, PhysicalDeviceType::INTEGRATED_GPU, 16384);
+        // same score as the integrated gpu
+        let discrete = device_info(2, PhysicalDeviceType::DISCRETE_GPU, 16384 - 900);
+        let cpu = device_info(3, PhysicalDeviceType::CPU, 8192);
+
+        let candidates = [integrated.clone(), discrete.clone(), cpu.clone()];
+        let selected = select_best_physical_device(&candidates).unwrap();
+        assert_eq!(selected.physical_device, integrated.physical_device);
+
+        let candidates = [cpu, discrete.clone(), integrated];
+        let selected = select_best_physical_device(&candidates).unwrap();
+        assert_eq!(selected.physical_device, discrete.physical_device);
+    }
+
+    #[test]
+    fn present_falls_back_to_a_separate_family() {
+        let queue_families = [
+            queue_family(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE),
+            queue_family(vk::QueueFlags::TRANSFER),
+        ];
+        let indices = choose_queue_family_indices(&queue_families, &[false, true]).unwrap();
+        assert_eq!(
+            indices,
+            QueueFamilyIndices {
+                graphics: 0,
+                present: 1,
+                transfer: Some(1),
+            }
+        );
+    }
+
+    #[test]
+    fn graphics_family_with_present_is_preferred() {
+        let queue_families = [
+            queue_family(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE),
+            queue_family(vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER),
+            queue_family(
+                vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER,
+            ),
+        ];
+        let indices = choose_queue_family_indices(&queue_families, &[false, true, true]).unwrap();
+        assert_eq!(
+            indices,
+            QueueFamilyIndices {
+                graphics: 2,
+                present: 2,
+                transfer: Some(1),
+            }
+        );
+    }
+
+    #[test]
+    fn no_present_support_is_an_error() {
+        let queue_families = [queue_family(
+            vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+        )];
+        assert!(choose_queue_family_indices(&queue_families, &[false]).is_err());
+    }
+}
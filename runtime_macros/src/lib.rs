@@ -0,0 +1,94 @@
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use naga::back::spv;
+use naga::front::glsl;
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use naga::ShaderStage;
+use proc_macro::{TokenStream, TokenTree};
+
+// include_spirv!("shaders/foo.vert") compiles a GLSL shader at build time and expands to
+// a `&'static [u32]` of SPIR-V words, the path is relative to the invoking crate's root
+// the stage is taken from the extension: .vert, .frag or .comp
+#[proc_macro]
+pub fn include_spirv(input: TokenStream) -> TokenStream {
+    match expand(input) {
+        Ok(tokens) => tokens,
+        Err(err) => format!("compile_error!({err:?})").parse().unwrap(),
+    }
+}
+
+fn expand(input: TokenStream) -> Result<TokenStream, String> {
+    let relative_path = parse_path_literal(input)?;
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR")
+        .map_err(|_| "CARGO_MANIFEST_DIR is not set".to_string())?;
+    let path = PathBuf::from(manifest_dir).join(&relative_path);
+
+    let stage = shader_stage(&path)?;
+    let source = std::fs::read_to_string(&path)
+        .map_err(|err| format!("failed to read shader {}: {err}", path.display()))?;
+    let words = compile(&relative_path, &source, stage)?;
+
+    let mut spirv = String::new();
+    for word in words {
+        let _ = write!(spirv, "{word:#010x}u32,");
+    }
+    // include_bytes makes cargo rebuild the invoking crate when the shader changes
+    let expanded = format!(
+        "{{ const _: &[u8] = include_bytes!({path:?}); const SPIRV: &[u32] = &[{spirv}]; SPIRV }}",
+        path = path.display().to_string()
+    );
+    expanded
+        .parse()
+        .map_err(|err| format!("failed to expand include_spirv: {err}"))
+}
+
+// only plain string literals, escapes aren't needed for paths
+fn parse_path_literal(input: TokenStream) -> Result<String, String> {
+    let mut tokens = input.into_iter();
+    let literal = match (tokens.next(), tokens.next()) {
+        (Some(TokenTree::Literal(literal)), None) => literal.to_string(),
+        _ => return Err("include_spirv expects a single string literal".to_string()),
+    };
+    literal
+        .strip_prefix('"')
+        .and_then(|e| e.strip_suffix('"'))
+        .filter(|e| !e.contains('\\'))
+        .map(str::to_string)
+        .ok_or_else(|| format!("include_spirv expects a plain string literal, got {literal}"))
+}
+
+fn shader_stage(path: &Path) -> Result<ShaderStage, String> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("vert") => Ok(ShaderStage::Vertex),
+        Some("frag") => Ok(ShaderStage::Fragment),
+        Some("comp") => Ok(ShaderStage::Compute),
+        _ => Err(format!(
+            "can't infer the shader stage of {}, expected a .vert, .frag or .comp file",
+            path.display()
+        )),
+    }
+}
+
+fn compile(name: &str, source: &str, stage: ShaderStage) -> Result<Vec<u32>, String> {
+    let module = glsl::Frontend::default()
+        .parse(&glsl::Options::from(stage), source)
+        .map_err(|errors| {
+            errors
+                .iter()
+                .map(|error| {
+                    let location = error.meta.location(source);
+                    format!(
+                        "{name}:{}:{}: {}",
+                        location.line_number, location.line_position, error.kind
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        })?;
+    let info = Validator::new(ValidationFlags::all(), Capabilities::all())
+        .validate(&module)
+        .map_err(|err| format!("{name}: validation failed: {err:?}"))?;
+    spv::write_vec(&module, &info, &spv::Options::default(), None)
+        .map_err(|err| format!("{name}: failed to write spir-v: {err}"))
+}